@@ -26,5 +26,8 @@ fn heap_allocation(b: &mut Bencher) {
     b.iter(|| Vec::<u32>::with_capacity(10));
 }
 
+// Define global allocator to trace memory allocation
+smbench_trace_memory!();
+
 smbench_group!(benchmark, fibonacci_20, heap_allocation);
 smbench_main!(benchmark);
@@ -1,16 +1,49 @@
-use std::sync::Arc;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Once};
+use std::time::Duration;
 
-use crate::bench::Bencher;
+use crate::bench::{Bencher, BenchmarkResult};
+use crate::budget::Budget;
+use crate::calibration;
+use crate::checkpoint::Checkpoint;
 use crate::common::{BenchmarkGroup, BenchmarkInfo};
-use crate::config::BenchmarkConfig;
-use crate::reporter::{Reporter, ReporterOptions};
+use crate::config::{BenchmarkConfig, MeasurementKind};
+use crate::error::Error;
+use crate::fmt;
+use crate::measurement::{Measurement, Unit};
+use crate::process::ProcessResults;
+use crate::profile::{self, Sampler};
+use crate::reporter::{MeasurementFormat, Reporter, ReporterOptions};
+use crate::rng;
+use crate::signal;
+use crate::stats::{Distribution, Normal};
+use crate::watchdog;
 
 pub struct App {
-    #[allow(dead_code)]
     config: Arc<BenchmarkConfig>,
     bencher: Bencher,
     reporters: Vec<Box<dyn Reporter>>,
     reporter_options: ReporterOptions,
+    /// group currently benchmarked
+    group: BenchmarkGroup,
+    /// results measured in child processes (only used if `processes > 1` or
+    /// `isolate` is set)
+    processes: Option<ProcessResults>,
+    /// time budget of the whole suite (only used if `total_time > 0`)
+    budget: Option<Budget>,
+    /// results saved by completed benchmarks (`None` if unavailable)
+    checkpoint: Option<Checkpoint>,
+    /// time per iteration of an empty loop (measured before the first
+    /// benchmark)
+    empty_loop: Option<Normal>,
+    /// number of benchmarks selected so far
+    selected: usize,
+    /// number of benchmarks which failed
+    failures: usize,
+    /// whether a benchmark was interrupted by Ctrl-C
+    interrupted: bool,
 }
 
 impl App {
@@ -20,71 +53,497 @@ impl App {
     }
 
     pub fn from_config(config: Arc<BenchmarkConfig>) -> Self {
+        if !config.merge.is_empty() {
+            merge_reports(&config.merge);
+        }
+
         let bencher = Bencher::new(Arc::clone(&config));
-        let reporter_options = ReporterOptions::from_config(&*config);
-        let reporters = config
-            .reporters_string
-            .split(",")
-            .map(|s| <dyn Reporter>::from_str(s))
-            .collect();
+        let mut reporter_options = ReporterOptions::from_config(&*config);
+        reporter_options.measurement = bencher.measurement().name().to_owned();
+        reporter_options.unit = bencher.measurement().unit();
+        #[cfg(target_arch = "x86_64")]
+        if config.measure == MeasurementKind::Cycles {
+            reporter_options.tsc_frequency = Some(crate::measurement::tsc::frequency());
+        }
+        // child processes use the calibration of the parent process
+        let timer = match config.timer {
+            Some((resolution, overhead)) => calibration::Timer {
+                resolution,
+                overhead,
+            },
+            None => calibration::timer(),
+        };
+        reporter_options.timer_resolution = timer.resolution;
+        reporter_options.timer_overhead = timer.overhead;
+        let reporters_string = if config.child {
+            "raw"
+        } else {
+            &*config.reporters_string
+        };
+        // profiling reports only the written profiles
+        let reporters = if config.profile_time > 0.0 {
+            Vec::new()
+        } else {
+            reporters_string
+                .split(",")
+                .map(|s| <dyn Reporter>::from_str(s))
+                .collect()
+        };
+        if (config.processes == 1 && !config.isolate) || config.child {
+            watchdog::spawn(config.child);
+        }
+        signal::install_handler();
+        let checkpoint = if config.resume && config.profile_time <= 0.0 {
+            Checkpoint::open(&config)
+        } else {
+            None
+        };
+
+        let empty_loop = config
+            .empty_loop
+            .map(|(mean, stderr)| Normal::new(mean, stderr));
 
         Self {
             config,
             bencher,
             reporters,
             reporter_options,
+            group: BenchmarkGroup::new("", "", Vec::new()),
+            processes: None,
+            budget: None,
+            checkpoint,
+            empty_loop,
+            selected: 0,
+            failures: 0,
+            interrupted: false,
         }
     }
 
+    /// Mean and standard error of the empty loop attached to results, which
+    /// are only comparable to values measured in seconds
+    fn empty_loop_estimate(&self) -> Option<(f64, f64)> {
+        self.empty_loop
+            .as_ref()
+            .filter(|_| self.reporter_options.unit == Unit::Seconds)
+            .map(|e| (e.mean(), e.variance().sqrt()))
+    }
+
+    fn report<F: Fn(&dyn Reporter)>(&self, f: F) {
+        self.reporters.iter().for_each(|r| f(&**r));
+    }
+
     pub fn bench_group(&mut self, group: &BenchmarkGroup) {
-        self.reporters
-            .iter()
-            .for_each(|r| r.on_group_init(group, &self.reporter_options));
+        if self.interrupted {
+            return;
+        }
+
+        if self.processes_enabled() && self.processes.is_none() {
+            let timer = calibration::Timer {
+                resolution: self.reporter_options.timer_resolution,
+                overhead: self.reporter_options.timer_overhead,
+            };
+            self.processes = Some(ProcessResults::collect(&self.config, timer));
+        }
+
+        self.group = group.clone();
+        self.report(|r| r.on_group_init(group, &self.reporter_options));
 
         for benchmark in group.benchmarks() {
-            #[cfg(feature = "regex")]
-            {
-                let reg = regex::Regex::new(&self.config.filter).unwrap();
-                if reg.is_match(benchmark.name()) {
-                    self.bench_single(benchmark);
-                }
+            if self.is_selected(group, benchmark) {
+                self.bench_single(benchmark);
             }
 
-            if !cfg!(feature = "regex")
-                && (self.config.filter.is_empty() || self.config.filter.contains(benchmark.name()))
-            {
-                self.bench_single(benchmark)
+            // reporters flush the results measured so far
+            if self.interrupted {
+                break;
+            }
+        }
+
+        self.report(|r| r.on_group_finish(group, &self.reporter_options));
+    }
+
+    /// Plan the time of each benchmark if the time budget of the whole suite
+    /// is given by `--total-time`. `groups` are the groups which will be
+    /// passed to `bench_group`.
+    pub fn plan(&mut self, groups: &[BenchmarkGroup]) {
+        if self.config.total_time <= 0.0 {
+            return;
+        }
+
+        let mut benchmarks: usize = 0;
+        for group in groups {
+            for benchmark in group.benchmarks() {
+                let checkpointed = self
+                    .checkpoint
+                    .as_ref()
+                    .is_some_and(|c| c.contains(group.name(), benchmark.name()));
+                if self.is_selected(group, benchmark) && !checkpointed {
+                    benchmarks += 1;
+                }
             }
         }
+        let benchmarks = benchmarks.saturating_sub(self.config.skip);
+
+        // child processes share the budget (see `ProcessResults::collect`)
+        let total_time = self.config.total_time / self.config.processes as f64;
+        let budget = Budget::new(&self.config, total_time, benchmarks);
+        if !self.config.child {
+            // more than `--total-time` if benchmarks get their minimum time
+            let (warmup_time, measurement_time) = budget.peek();
+            let estimated_time = (warmup_time + measurement_time)
+                * benchmarks as f64
+                * self.config.processes as f64;
+            eprintln!(
+                "Estimated total time: {} ({} benchmark(s), {} warm-up + {} measurement each)",
+                fmt::time(estimated_time),
+                benchmarks,
+                fmt::time(warmup_time),
+                fmt::time(measurement_time)
+            );
+        }
+
+        if !self.processes_enabled() {
+            self.budget = Some(budget);
+        }
+    }
+
+    /// Measure `measurement` instead of wall-clock time in the following
+    /// benchmarks. A copy of it is shared with reporters, which format the
+    /// values by `Measurement::format`.
+    pub fn set_measurement<M>(&mut self, measurement: M)
+    where
+        M: Measurement + Clone + Sync + 'static,
+    {
+        self.reporter_options.measurement = measurement.name().to_owned();
+        self.reporter_options.unit = measurement.unit();
+        self.reporter_options.format = Some(MeasurementFormat(Arc::new(measurement.clone())));
+        self.bencher.set_measurement(Box::new(measurement));
+    }
+
+    /// Whether benchmarks are measured in child processes by this process
+    fn processes_enabled(&self) -> bool {
+        (self.config.processes > 1 || self.config.isolate)
+            && !self.config.child
+            && self.config.profile_time <= 0.0
+    }
+
+    fn is_selected(&self, group: &BenchmarkGroup, info: &BenchmarkInfo) -> bool {
+        #[cfg(feature = "regex")]
+        let matched = regex::Regex::new(&self.config.filter)
+            .unwrap()
+            .is_match(info.name());
+
+        #[cfg(not(feature = "regex"))]
+        let matched = self.config.filter.is_empty() || self.config.filter.contains(info.name());
 
-        self.reporters
-            .iter()
-            .for_each(|r| r.on_group_finish(group, &self.reporter_options));
+        matched && self.in_shard(group, info)
+    }
+
+    /// Whether the benchmark belongs to the shard selected by `--shard`.
+    ///
+    /// Benchmarks are assigned to shards by the hash of their group and name,
+    /// so every shard selects the same benchmarks on any machine.
+    fn in_shard(&self, group: &BenchmarkGroup, info: &BenchmarkInfo) -> bool {
+        let (index, count) = match self.config.shard {
+            Some(shard) => shard,
+            None => return true,
+        };
+
+        let hash = rng::stable_hash(&format!("{}/{}", group.name(), info.name()));
+        hash % count as u64 == (index - 1) as u64
     }
 
     pub fn bench_single(&mut self, info: &BenchmarkInfo) {
-        self.reporters
-            .iter()
-            .for_each(|r| r.on_benchmark_start(info, &self.reporter_options));
+        let group = self.group.name();
+        if let Some(result) = self
+            .checkpoint
+            .as_mut()
+            .and_then(|c| c.take(group, info.name()))
+        {
+            // child processes leave resumed results to the parent process
+            if !self.config.child {
+                self.report(|r| r.on_benchmark_start(info, &self.reporter_options));
+                self.report(|r| r.on_benchmark_complete(info, &result, &self.reporter_options));
+            }
+            return;
+        }
+
+        // benchmarks already measured by previous child processes
+        self.selected += 1;
+        if self.selected <= self.config.skip {
+            return;
+        }
+
+        // isolated child processes measure only one benchmark
+        if self.config.child && self.config.isolate && self.selected > self.config.skip + 1 {
+            return;
+        }
+
+        if self.config.profile_time > 0.0 {
+            self.profile_single(info);
+            return;
+        }
+
+        self.report(|r| r.on_benchmark_start(info, &self.reporter_options));
+
+        let result = match self.processes {
+            Some(ref mut processes) => processes.take(self.group.name(), info.name()),
+            None => {
+                // child processes use the empty loop measured by the parent
+                if self.empty_loop.is_none() && !self.config.child {
+                    self.empty_loop = calibration::empty_loop(&self.config);
+                }
+
+                let timeout = if self.config.timeout > 0.0 {
+                    Some(Duration::from_secs_f64(self.config.timeout))
+                } else {
+                    None
+                };
+                watchdog::watch(&self.group, info, timeout);
+
+                let key = format!("{}/{}", self.group.name(), info.name());
+                self.bencher
+                    .set_seed(self.config.seed ^ rng::stable_hash(&key));
+
+                if let Some(ref mut budget) = self.budget {
+                    let (warmup_time, measurement_time) = budget.next();
+                    self.bencher.set_time(warmup_time, measurement_time);
+                }
+
+                let bencher = &mut self.bencher;
+                let reporters = &self.reporters;
+                let options = &self.reporter_options;
+                let on_measurement_start = |sample_size, estimated_time| {
+                    for r in reporters.iter() {
+                        r.on_measurement_start(info, sample_size, estimated_time, options);
+                    }
+                };
+                let result = catch_panic(|| bencher.auto_bench(info.func, &on_measurement_start));
+                watchdog::unwatch();
+
+                let empty_loop = self.empty_loop_estimate();
+                result.map(|result| BenchmarkResult {
+                    empty_loop,
+                    ..result
+                })
+            }
+        };
 
-        let result = match self.bencher.auto_bench(info.func) {
+        let result = match result {
             Ok(res) => res,
             Err(e) => {
-                eprintln!("Benchmark failed: {:?}", e);
+                if e == Error::Interrupted {
+                    self.interrupted = true;
+                    self.report(|r| r.on_terminated(info, &self.reporter_options));
+                }
+
+                self.failures += 1;
+                self.report(|r| r.on_benchmark_failed(info, &e, &self.reporter_options));
                 return;
             }
         };
 
-        self.reporters
-            .iter()
-            .for_each(|r| r.on_benchmark_complete(info, &result, &self.reporter_options));
+        if let Some(ref mut checkpoint) = self.checkpoint {
+            checkpoint.append(self.group.name(), info.name(), &result);
+        }
+        self.report(|r| r.on_benchmark_complete(info, &result, &self.reporter_options));
+    }
+
+    /// Profile the benchmark for `profile_time` seconds and write its sampled
+    /// stacks into the output directory
+    fn profile_single(&mut self, info: &BenchmarkInfo) {
+        let seconds = self.config.profile_time;
+        let key = format!("{}/{}", self.group.name(), info.name());
+        self.bencher
+            .set_seed(self.config.seed ^ rng::stable_hash(&key));
+
+        eprintln!("Profiling {} for {}", key, fmt::time(seconds));
+        let mut sampler = match Sampler::new(seconds) {
+            Ok(sampler) => sampler,
+            Err(e) => {
+                eprintln!("Warning: failed to start the profiler: {}", e);
+                self.failures += 1;
+                return;
+            }
+        };
+
+        let bencher = &mut self.bencher;
+        let result = catch_panic(|| bencher.profile(info.func, seconds, &mut sampler));
+        let profile = match result {
+            Ok(profile) => profile,
+            Err(e) => {
+                if e == Error::Interrupted {
+                    self.interrupted = true;
+                }
+                eprintln!("Profiling {} failed: {}", key, e);
+                self.failures += 1;
+                return;
+            }
+        };
+
+        let path = match profile::output_path(self.group.name(), info.name()) {
+            Some(path) => path,
+            None => {
+                eprintln!("Warning: failed to detect the output directory");
+                self.failures += 1;
+                return;
+            }
+        };
+        if let Err(e) = profile.write_folded(&path) {
+            eprintln!("Warning: failed to write {}: {}", path.display(), e);
+            self.failures += 1;
+            return;
+        }
+
+        eprintln!(
+            "Wrote {} sample(s) ({} dropped) to {}",
+            profile.samples(),
+            profile.dropped(),
+            path.display()
+        );
+        if profile.samples() == 0 {
+            eprintln!("Warning: no samples were taken. the benchmark may not use the CPU");
+        }
+    }
+
+    /// Finish benchmarks. The process exits with non-zero status if any of
+    /// benchmarks failed or benchmarks were interrupted.
+    pub fn finish(self) {
+        let failures = self.failures;
+        drop(self);
+
+        if signal::interrupted() {
+            std::process::exit(130);
+        } else if failures > 0 {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Merge JSON reports of shards into `benchmark.json` and exit
+fn merge_reports(inputs: &[String]) -> ! {
+    #[cfg(feature = "json")]
+    {
+        let mut path = crate::common::create_output_dir().expect("Failed to detect 'target_dir'");
+        path.push("benchmark.json");
+        crate::reporter::merge_reports(inputs, &path).expect("Failed to merge reports");
+        eprintln!("Merged {} report(s) into {}", inputs.len(), path.display());
+        std::process::exit(0);
+    }
+
+    #[cfg(not(feature = "json"))]
+    {
+        let _ = inputs;
+        panic!("--merge requires the json feature of smbench");
     }
+}
 
-    pub fn finish(self) {}
+thread_local! {
+    static CATCHING_PANIC: Cell<bool> = const { Cell::new(false) };
+    static PANIC_LOCATION: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Run the benchmark and convert panics into `Error::BenchmarkPanicked`.
+///
+/// The default panic message is suppressed (unless `RUST_BACKTRACE` is set)
+/// because the panic is reported by reporters.
+fn catch_panic<T, F>(f: F) -> Result<T, Error>
+where
+    F: FnOnce() -> Result<T, Error>,
+{
+    static INSTALL_HOOK: Once = Once::new();
+    INSTALL_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING_PANIC.with(|c| c.get()) {
+                return default_hook(info);
+            }
+
+            let location = info.location().map(|l| l.to_string());
+            PANIC_LOCATION.with(|l| *l.borrow_mut() = location);
+            if std::env::var_os("RUST_BACKTRACE").is_some() {
+                default_hook(info);
+            }
+        }));
+    });
+
+    CATCHING_PANIC.with(|c| c.set(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING_PANIC.with(|c| c.set(false));
+
+    result.unwrap_or_else(|payload| {
+        let mut message = panic_message(&*payload);
+        if let Some(location) = PANIC_LOCATION.with(|l| l.borrow_mut().take()) {
+            message = format!("{} at {}", message, location);
+        }
+        Err(Error::BenchmarkPanicked(message))
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
 }
 
 impl Drop for App {
     fn drop(&mut self) {
-        self.reporters.iter().for_each(|r| r.on_finish(&self.reporter_options));
+        self.report(|r| r.on_finish(&self.reporter_options));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reporter::{format_result_line, parse_raw_line, RawEvent};
+
+    /// The empty loop passed by the parent process is reported back unchanged
+    #[test]
+    fn empty_loop_of_child_process() {
+        let config = BenchmarkConfig {
+            child: true,
+            empty_loop: Some((2.5e-10, 1e-12)),
+            ..BenchmarkConfig::default()
+        };
+        let app = App::from_config(Arc::new(config));
+
+        let result = BenchmarkResult {
+            measurements: vec![(10, 1.5e-6)],
+            wall_times: Vec::new(),
+            counters: Vec::new(),
+            metrics: Vec::new(),
+            runs: Vec::new(),
+            layouts: Vec::new(),
+            first_calls: Vec::new(),
+            input_pool: None,
+            latency: None,
+            rates: Vec::new(),
+            stack: None,
+            empty_loop: app.empty_loop_estimate(),
+        };
+        let line = format_result_line("group", "bench", &result);
+        match parse_raw_line(&line) {
+            Some(RawEvent::Complete { result, .. }) => {
+                assert_eq!(result.unwrap().empty_loop, Some((2.5e-10, 1e-12)));
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    /// The timer is not calibrated again in child processes
+    #[test]
+    fn timer_of_child_process() {
+        let config = BenchmarkConfig {
+            child: true,
+            timer: Some((3.5e-8, 1e-7)),
+            ..BenchmarkConfig::default()
+        };
+        let app = App::from_config(Arc::new(config));
+        assert_eq!(app.reporter_options.timer_resolution, 3.5e-8);
+        assert_eq!(app.reporter_options.timer_overhead, 1e-7);
     }
 }
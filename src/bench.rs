@@ -1,21 +1,258 @@
 use std::cmp;
-use std::sync::Arc;
+use std::panic;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::cache::CacheEvictor;
 use crate::common::black_box;
-use crate::config::BenchmarkConfig;
+use crate::config::{BenchmarkConfig, BenchmarkMode, MeasurementKind, WarmupMode};
+use crate::control::{Controller, Recording};
 use crate::error::Error;
+use crate::histogram::Histogram;
+use crate::layout::{MemoryLayout, SAMPLES_PER_LAYOUT};
+use crate::measurement::{self, Measurement, PerfCounters, Unit, WallTime};
+use crate::memory;
+use crate::profile::{Profile, Sampler};
+use crate::rng::{mix, Rng};
+use crate::signal;
+use crate::stack;
+use crate::stats;
+use crate::watchdog;
+
+/// Minimum duration of warm-up batches compared by the steady-state detection
+/// in nanoseconds
+const STEADY_STATE_BATCH_TIME: u64 = 10_000_000;
+/// Number of consecutive batches which must be stable
+const STEADY_STATE_WINDOW: usize = 10;
+/// Maximum coefficient of variation of the time per iteration in steady state
+const STEADY_STATE_MAX_CV: f64 = 0.02;
+/// Lower bound of the time per iteration estimated by warm-up in nanoseconds
+const MIN_NS_PER_ITER: f64 = 0.01;
+/// Number of single calls timed after warm-up in latency mode to estimate the
+/// cost of a call including the overhead of timing it
+const LATENCY_PROBE_CALLS: usize = 10;
+/// Fraction of the offered rate below which the throughput of open-loop mode
+/// counts as saturated
+const SATURATION_THROUGHPUT: f64 = 0.95;
+/// Minimum duration of one call of the benchmark function while it is
+/// profiled, so that its setup is a small part of the samples
+const PROFILE_BATCH_TIME: Duration = Duration::from_millis(10);
+/// Maximum number of iterations of one call while profiling
+const PROFILE_MAX_ITERS: usize = 1 << 30;
+/// Counters of the number and the bytes of heap allocations, measured if
+/// `smbench_trace_memory!()` is used
+const ALLOCATION_COUNTERS: [&str; 2] = ["allocations", "allocated_bytes"];
+/// Time spent spinning instead of sleeping until the next call of open-loop
+/// mode is due
+const SPIN_TIME: Duration = Duration::from_millis(1);
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct BenchmarkResult {
     pub measurements: Vec<(usize, f64)>,
+    /// Wall-clock time of each measurement, recorded if the benchmark measures
+    /// something else than wall-clock time. Empty otherwise.
+    pub wall_times: Vec<f64>,
+    /// Values of the performance counters given by `--perf-events` and of the
+    /// counters reported by `Bencher::report_counter` for each measurement
+    pub counters: Vec<(String, Vec<f64>)>,
+    /// Name, unit and values of the metrics reported by
+    /// `Bencher::report_metric`, one value per measurement which reported it
+    pub metrics: Vec<(String, Unit, Vec<f64>)>,
+    /// Number of measurements taken by each process, in order of execution.
+    /// Empty if all measurements come from a single process.
+    pub runs: Vec<usize>,
+    /// Index of the randomized memory layout of each measurement among the
+    /// layouts of its process. Empty if the memory layout was not randomized.
+    pub layouts: Vec<usize>,
+    /// Values of the first calls of the routine in each process, measured
+    /// before warm-up (see `--cold-start`). Empty if not measured.
+    pub first_calls: Vec<f64>,
+    /// Number of inputs passed to `Bencher::iter_with_inputs`, if used
+    pub input_pool: Option<usize>,
+    /// Values of every single call, measured in latency mode (`--mode latency`)
+    pub latency: Option<Histogram>,
+    /// Latency of calls issued at each rate in open-loop mode, in order of the
+    /// sweep
+    pub rates: Vec<RateResult>,
+    /// Maximum stack depth in bytes of one call of the benchmark function,
+    /// measured with `--measure-stack`
+    pub stack: Option<usize>,
+    /// Mean and standard error of the time per iteration of an empty loop,
+    /// measured in the same process
+    pub empty_loop: Option<(f64, f64)>,
 }
 
+impl BenchmarkResult {
+    /// Merge the results of the same benchmark measured in different processes
+    pub fn merge(results: Vec<BenchmarkResult>) -> BenchmarkResult {
+        let mut merged = BenchmarkResult {
+            measurements: Vec::new(),
+            wall_times: Vec::new(),
+            counters: Vec::new(),
+            metrics: Vec::new(),
+            runs: Vec::with_capacity(results.len()),
+            layouts: Vec::new(),
+            first_calls: Vec::new(),
+            input_pool: None,
+            latency: None,
+            rates: Vec::new(),
+            stack: None,
+            empty_loop: None,
+        };
+
+        for result in results {
+            if result.runs.is_empty() {
+                merged.runs.push(result.measurements.len());
+            } else {
+                merged.runs.extend_from_slice(&result.runs);
+            }
+            merged.layouts.extend(result.layouts);
+            merged.first_calls.extend(result.first_calls);
+            merged.input_pool = merged.input_pool.or(result.input_pool);
+            merged.stack = cmp::max(merged.stack, result.stack);
+            if let Some(latency) = result.latency {
+                match merged.latency {
+                    Some(ref mut merged) => merged.merge(&latency),
+                    None => merged.latency = Some(latency),
+                }
+            }
+            merged.empty_loop = merged.empty_loop.or(result.empty_loop);
+            for rate in result.rates {
+                match merged.rates.iter_mut().find(|r| r.rate == rate.rate) {
+                    Some(merged) => {
+                        merged.elapsed += rate.elapsed;
+                        merged.latency.merge(&rate.latency);
+                    }
+                    None => merged.rates.push(rate),
+                }
+            }
+            // counters which a process did not report count zero in it
+            let offset = merged.measurements.len();
+            let len = offset + result.measurements.len();
+            merged.measurements.extend(result.measurements);
+            merged.wall_times.extend(result.wall_times);
+            for (name, values) in result.counters {
+                let merged_values = match merged.counters.iter().position(|c| c.0 == name) {
+                    Some(idx) => &mut merged.counters[idx].1,
+                    None => {
+                        merged.counters.push((name, vec![0.0; offset]));
+                        &mut merged.counters.last_mut().unwrap().1
+                    }
+                };
+                merged_values.extend(values);
+            }
+            for counter in merged.counters.iter_mut() {
+                counter.1.resize(len, 0.0);
+            }
+            for (name, unit, values) in result.metrics {
+                match merged.metrics.iter_mut().find(|m| m.0 == name) {
+                    Some(metric) => metric.2.extend(values),
+                    None => merged.metrics.push((name, unit, values)),
+                }
+            }
+        }
+
+        merged
+    }
+
+    /// Iterate over the measurements taken by each process
+    pub fn runs(&self) -> impl Iterator<Item = &[(usize, f64)]> {
+        let mut rest = &*self.measurements;
+        let single = if self.runs.is_empty() {
+            Some(rest)
+        } else {
+            None
+        };
+
+        self.runs
+            .iter()
+            .map(move |&n| {
+                let (run, tail) = rest.split_at(n);
+                rest = tail;
+                run
+            })
+            .chain(single)
+    }
+}
+
+/// Calls issued at a fixed rate in open-loop mode (`--mode open-loop`)
 #[derive(Clone, Debug, PartialEq)]
+pub struct RateResult {
+    /// offered calls per second
+    pub rate: f64,
+    /// time from the first intended start to the completion of the last call
+    /// in seconds
+    pub elapsed: f64,
+    /// wall-clock time from the intended start to the completion of each call
+    pub latency: Histogram,
+}
+
+impl RateResult {
+    /// Completed calls per second
+    pub fn throughput(&self) -> f64 {
+        self.latency.count() as f64 / self.elapsed
+    }
+
+    /// Whether the calls could not keep up with the offered rate
+    pub fn is_saturated(&self) -> bool {
+        self.throughput() < self.rate * SATURATION_THROUGHPUT
+    }
+}
+
+/// Calls executed by one worker of open-loop mode
+struct ServedCalls {
+    latency: Histogram,
+    value: f64,
+    dur: Duration,
+    calls: usize,
+    /// completion of the last call
+    end: Option<Instant>,
+}
+
+/// Runs and times the benchmark function.
+///
+/// `Bencher` is neither `Clone` nor `PartialEq`, since it owns the measurement
+/// and the resources opened for the run (e.g. performance counters).
+#[derive(Debug)]
 pub struct Bencher {
     measure_time: bool,
+    /// value of the last sample measured by `measurement`
+    value: f64,
+    /// elapsed time of the last sample
     dur: Duration,
+    /// elapsed time of the last call including untimed work (e.g. setup)
+    wall: Duration,
     iterations: usize,
+    /// size of the input pool and the index of the next input of
+    /// `iter_with_inputs`
+    input_pool: Option<usize>,
+    input_offset: usize,
+    /// warm-up time and measurement time of the next benchmark in seconds
+    warmup_time: f64,
+    measurement_time: f64,
+    /// evicts CPU caches before timing (only used with `--cache-cold`)
+    evictor: Option<CacheEvictor>,
+    measurement: Box<dyn Measurement>,
+    /// whether wall-clock time is recorded next to `measurement`
+    record_wall_time: bool,
+    /// performance counters of the thread creating the bencher (only used
+    /// with `--perf-events`)
+    perf: Option<PerfCounters>,
+    /// external profiler enabled during measurement (only used with
+    /// `--profiler-control`)
+    controller: Option<Controller>,
+    /// whether performance counters keep counting across samples, so that they
+    /// count all calls of a latency sample
+    accumulate_counters: bool,
+    /// counters and metrics reported by the benchmark function for the
+    /// running sample
+    reported_counters: Vec<(String, f64)>,
+    reported_metrics: Vec<(String, Unit, f64)>,
+    /// seed of the running benchmark and the generator returned by `rng()`
+    seed: u64,
+    rng: Rng,
     config: Arc<BenchmarkConfig>,
 }
 
@@ -23,54 +260,637 @@ impl Bencher {
     pub fn new(config: Arc<BenchmarkConfig>) -> Bencher {
         Bencher {
             measure_time: true,
+            value: 0.0,
             dur: Duration::new(0, 0),
+            wall: Duration::new(0, 0),
             iterations: 1,
+            input_pool: None,
+            input_offset: 0,
+            warmup_time: config.warmup_time,
+            measurement_time: config.measurement_time,
+            evictor: if config.cache_cold {
+                Some(CacheEvictor::new())
+            } else {
+                None
+            },
+            measurement: measurement::from_kind(config.measure),
+            record_wall_time: config.measure != MeasurementKind::WallTime,
+            perf: if config.perf_events.is_empty() {
+                None
+            } else {
+                Some(PerfCounters::open(&config.perf_events, !config.child))
+            },
+            controller: config.profiler_control.as_ref().and_then(|target| {
+                Controller::open(target)
+                    .map_err(|e| eprintln!("Warning: failed to open the profiler control: {}", e))
+                    .ok()
+            }),
+            accumulate_counters: false,
+            reported_counters: Vec::new(),
+            reported_metrics: Vec::new(),
+            seed: config.seed,
+            rng: Rng::new(config.seed),
             config,
         }
     }
 
+    /// Set the warm-up time and measurement time of the next benchmark
+    pub(crate) fn set_time(&mut self, warmup_time: f64, measurement_time: f64) {
+        self.warmup_time = warmup_time;
+        self.measurement_time = measurement_time;
+    }
+
+    /// Replace what the following benchmarks measure
+    pub(crate) fn set_measurement(&mut self, measurement: Box<dyn Measurement>) {
+        // wall-clock time is recorded beside any other measurement
+        self.record_wall_time = measurement.name() != WallTime::new().name();
+        self.measurement = measurement;
+    }
+
+    pub(crate) fn measurement(&self) -> &dyn Measurement {
+        &*self.measurement
+    }
+
+    /// Set the seed of the generator returned by `rng()` for the next benchmark
+    pub(crate) fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Rng::new(seed);
+    }
+
+    /// Random number generator for generating inputs of the benchmark.
+    ///
+    /// The generator is seeded from `--seed` and the name of the benchmark, and
+    /// is reset after every call of `iter` and its variants, so each call of
+    /// the benchmark function generates the same inputs.
+    #[inline]
+    pub fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    /// Report a value measured by the benchmark function in addition to time,
+    /// e.g. the compression ratio of the data compressed by the routine.
+    ///
+    /// The value applies to the sample measured by the current call of the
+    /// benchmark function and replaces values reported earlier in the same
+    /// call. Reporters show the mean over all samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is empty or contains whitespace or `=`.
+    pub fn report_metric(&mut self, name: &str, value: f64, unit: Unit) {
+        check_metric_name(name);
+        match self.reported_metrics.iter_mut().find(|m| m.0 == name) {
+            Some(metric) => metric.2 = value,
+            None => self.reported_metrics.push((name.to_owned(), unit, value)),
+        }
+    }
+
+    /// Add `n` to a counter of the sample measured by the current call of the
+    /// benchmark function, e.g. the number of cache hits of all iterations.
+    ///
+    /// Like performance counters, counters are reported per iteration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` is empty or contains whitespace or `=`, or if it is
+    /// the name of an opened perf event (see `--perf-events`) or of a counter
+    /// of `smbench_trace_memory!()`.
+    pub fn report_counter(&mut self, name: &str, n: u64) {
+        check_metric_name(name);
+        let perf_events = self.perf.as_ref().map_or(&[][..], |p| p.names());
+        let tracing = memory::allocations().is_some();
+        let reserved = perf_events.iter().any(|e| e == name)
+            || (tracing && ALLOCATION_COUNTERS.contains(&name));
+        assert!(
+            !reserved,
+            "counter {} collides with a counter measured by smbench",
+            name
+        );
+        self.add_counter(name, n);
+    }
+
+    fn add_counter(&mut self, name: &str, n: u64) {
+        match self.reported_counters.iter_mut().find(|c| c.0 == name) {
+            Some(counter) => counter.1 += n as f64,
+            None => self.reported_counters.push((name.to_owned(), n as f64)),
+        }
+    }
+
+    /// Set the time limit of the running benchmark, overriding `--timeout`.
+    /// The limit is measured from the start of the benchmark.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        watchdog::set_timeout(timeout);
+    }
+
+    /// Measure `inner` called in a loop.
+    ///
+    /// With `--cache-cold`, CPU caches are evicted before every iteration,
+    /// which is timed separately like `iter_with_setup`.
     #[inline]
     pub fn iter<T, F>(&mut self, mut inner: F)
     where
         F: FnMut() -> T,
     {
+        if self.evictor.is_some() {
+            return self.iter_with_setup(|| (), |()| inner());
+        }
+
+        let wall_start = Instant::now();
+        self.enable_counters(true);
         let start = Instant::now();
+        let m = self.measurement.start();
         let k = self.iterations;
         for _ in 0..k {
             black_box(inner());
         }
+        self.value = self.measurement.end(m);
         self.dur = start.elapsed();
+        self.disable_counters();
+        self.wall = wall_start.elapsed();
+        self.finish_iter();
+    }
+
+    /// Measure `inner` called with the inputs in `inputs` in turn.
+    ///
+    /// Calling the routine with the same input in every iteration lets branch
+    /// predictors and caches learn the input, so pass a pool of different
+    /// inputs to measure realistic performance. Consecutive samples continue
+    /// from the input following the last one used.
+    ///
+    /// With `--cache-cold`, CPU caches are evicted before every iteration,
+    /// which is timed separately like `iter_with_setup`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `inputs` is empty.
+    #[inline]
+    pub fn iter_with_inputs<I, T, F>(&mut self, inputs: &[I], mut inner: F)
+    where
+        F: FnMut(&I) -> T,
+    {
+        assert!(!inputs.is_empty(), "input pool must not be empty");
+        self.input_pool = Some(inputs.len());
+
+        let mut offset = self.input_offset % inputs.len();
+        if self.evictor.is_some() {
+            let next = || {
+                let input = &inputs[offset];
+                offset = (offset + 1) % inputs.len();
+                input
+            };
+            self.iter_with_setup(next, inner);
+            self.input_offset = offset;
+            return;
+        }
+
+        let wall_start = Instant::now();
+        let mut remaining = self.iterations;
+
+        self.enable_counters(true);
+        let start = Instant::now();
+        let m = self.measurement.start();
+        while remaining > 0 {
+            let n = cmp::min(remaining, inputs.len() - offset);
+            for input in &inputs[offset..offset + n] {
+                black_box(inner(input));
+            }
+            remaining -= n;
+            offset = (offset + n) % inputs.len();
+        }
+        self.value = self.measurement.end(m);
+        self.dur = start.elapsed();
+        self.disable_counters();
+        self.wall = wall_start.elapsed();
+        self.input_offset = offset;
+        self.finish_iter();
+    }
+
+    /// Call `setup` before each iteration and measure only `routine`, which
+    /// takes the value returned by `setup`.
+    ///
+    /// With `--cache-cold`, CPU caches are evicted before every iteration, so
+    /// `routine` runs with cold caches.
+    #[inline]
+    pub fn iter_with_setup<I, O, S, R>(&mut self, mut setup: S, mut routine: R)
+    where
+        S: FnMut() -> I,
+        R: FnMut(I) -> O,
+    {
+        let start = Instant::now();
+        let mut elapsed = Duration::new(0, 0);
+        let mut value = self.measurement.zero();
+        for i in 0..self.iterations {
+            let input = black_box(setup());
+            if let Some(ref mut evictor) = self.evictor {
+                evictor.evict();
+            }
+
+            self.enable_counters(i == 0);
+            let routine_start = Instant::now();
+            let m = self.measurement.start();
+            let output = black_box(routine(input));
+            let v = self.measurement.end(m);
+            elapsed += routine_start.elapsed();
+            self.disable_counters();
+            value = self.measurement.add(value, v);
+            drop(output);
+        }
+        self.value = value;
+        self.dur = elapsed;
+        self.wall = start.elapsed();
+        self.finish_iter();
+    }
+
+    /// Start counting performance events, from zero if `reset` is set
+    #[inline]
+    fn enable_counters(&mut self, reset: bool) {
+        if let Some(ref mut perf) = self.perf {
+            if reset && !self.accumulate_counters {
+                perf.reset();
+            }
+            perf.enable();
+        }
+    }
+
+    #[inline]
+    fn disable_counters(&mut self) {
+        if let Some(ref mut perf) = self.perf {
+            perf.disable();
+        }
+    }
+
+    /// Mark the benchmark function as valid and reset the generator for the
+    /// next call
+    #[inline]
+    fn finish_iter(&mut self) {
         self.measure_time = true;
+        self.rng = Rng::new(self.seed);
+    }
+
+    /// Call `f` with a single iteration `calls` times and record the value of
+    /// every call in `histogram`. The value of the sample is the sum of the
+    /// values of the calls.
+    fn measure_calls(&mut self, f: fn(&mut Bencher), calls: usize, histogram: &mut Histogram) {
+        if let Some(ref mut perf) = self.perf {
+            perf.reset();
+        }
+        self.accumulate_counters = true;
+        self.iterations = 1;
+
+        let mut value = self.measurement.zero();
+        let mut dur = Duration::new(0, 0);
+        for _ in 0..calls {
+            f(self);
+            histogram.record(self.value);
+            value = self.measurement.add(value, self.value);
+            dur += self.dur;
+        }
+
+        self.accumulate_counters = false;
+        self.iterations = calls;
+        self.value = value;
+        self.dur = dur;
+    }
+
+    /// Call `f` with a single iteration at `rate` calls per second until the
+    /// measurement time has passed.
+    ///
+    /// A scheduler thread issues calls at fixed intended start times
+    /// regardless of when previous calls complete, and `--workers` threads
+    /// (this one and additional ones) execute them. Calls wait in a queue while
+    /// all workers are busy, and the latency of each call is measured from its
+    /// intended start, so time spent queueing behind slow calls is counted
+    /// instead of omitted. Calls not started by the end of the measurement
+    /// time are dropped, so the throughput of a saturated routine falls below
+    /// `rate`.
+    ///
+    /// Additional workers measure what `--measure` selects and count
+    /// performance events on their own threads, which are added to the
+    /// counters of this thread.
+    fn measure_rate(&mut self, f: fn(&mut Bencher), rate: f64) -> RateResult {
+        if let Some(ref mut perf) = self.perf {
+            perf.reset();
+        }
+        self.accumulate_counters = true;
+        self.iterations = 1;
+
+        // additional workers must not open the external profiler again
+        let config = BenchmarkConfig {
+            perf_events: Vec::new(),
+            profiler_control: None,
+            ..(*self.config).clone()
+        };
+        let perf_events = self.perf.as_ref().map_or(Vec::new(), |p| p.names().to_vec());
+        let seed = self.seed;
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs_f64(self.measurement_time);
+        let (sender, receiver) = mpsc::channel();
+        let queue = Mutex::new(receiver);
+
+        let served = thread::scope(|scope| {
+            scope.spawn(move || {
+                let mut calls = 0;
+                loop {
+                    let intended = start + Duration::from_secs_f64(calls as f64 / rate);
+                    if intended >= deadline || stopped() {
+                        break;
+                    }
+                    wait_until(intended);
+                    if sender.send(intended).is_err() {
+                        break;
+                    }
+                    calls += 1;
+                }
+            });
+
+            let queue = &queue;
+            let workers: Vec<_> = (1..self.config.workers)
+                .map(|_| {
+                    let config = config.clone();
+                    let perf_events = perf_events.clone();
+                    scope.spawn(move || {
+                        let mut bencher = Bencher::new(Arc::new(config));
+                        bencher.set_seed(seed);
+                        if !perf_events.is_empty() {
+                            bencher.perf = Some(PerfCounters::open(&perf_events, false));
+                        }
+                        bencher.accumulate_counters = true;
+                        let served = bencher.serve_calls(f, queue, deadline);
+
+                        let mut counters = bencher.reported_counters;
+                        if let Some(ref mut perf) = bencher.perf {
+                            let names = perf.names().to_vec();
+                            let counts = perf.read().iter().map(|&n| n as f64);
+                            counters.extend(names.into_iter().zip(counts));
+                        }
+                        (served, counters, bencher.reported_metrics)
+                    })
+                })
+                .collect();
+
+            let mut served = self.serve_calls(f, queue, deadline);
+            for worker in workers {
+                let (other, counters, metrics) = match worker.join() {
+                    Ok(worker) => worker,
+                    Err(payload) => panic::resume_unwind(payload),
+                };
+                served.latency.merge(&other.latency);
+                served.value = self.measurement.add(served.value, other.value);
+                served.dur += other.dur;
+                served.calls += other.calls;
+                served.end = cmp::max(served.end, other.end);
+
+                // counts of performance events are added to the rows of the
+                // same events by `auto_bench`
+                for (name, n) in counters {
+                    match self.reported_counters.iter_mut().find(|c| c.0 == name) {
+                        Some(counter) => counter.1 += n,
+                        None => self.reported_counters.push((name, n)),
+                    }
+                }
+                for metric in metrics {
+                    if !self.reported_metrics.iter().any(|m| m.0 == metric.0) {
+                        self.reported_metrics.push(metric);
+                    }
+                }
+            }
+            served
+        });
+
+        self.accumulate_counters = false;
+        self.iterations = served.calls;
+        self.value = served.value;
+        self.dur = served.dur;
+
+        RateResult {
+            rate,
+            elapsed: served.end.map_or(0.0, |end| (end - start).as_secs_f64()),
+            latency: served.latency,
+        }
     }
 
-    fn warm_up(&mut self, how_long: Duration, f: fn(&mut Bencher)) -> f64 {
+    /// Execute calls issued by the scheduler of open-loop mode until it stops.
+    /// Calls received after `deadline` are dropped.
+    fn serve_calls(
+        &mut self,
+        f: fn(&mut Bencher),
+        queue: &Mutex<Receiver<Instant>>,
+        deadline: Instant,
+    ) -> ServedCalls {
+        let mut served = ServedCalls {
+            latency: Histogram::new(),
+            value: self.measurement.zero(),
+            dur: Duration::new(0, 0),
+            calls: 0,
+            end: None,
+        };
+        self.iterations = 1;
+
+        loop {
+            // other workers may take calls while this one is busy
+            let next = queue.lock().unwrap().recv();
+            let intended = match next {
+                Ok(intended) => intended,
+                Err(_) => break,
+            };
+            if Instant::now() >= deadline || stopped() {
+                continue;
+            }
+
+            f(self);
+            let end = Instant::now();
+
+            // untimed work of the benchmark function (e.g. setup) is not part of
+            // the call
+            let untimed = self.wall.saturating_sub(self.dur);
+            served
+                .latency
+                .record((end - intended).saturating_sub(untimed).as_secs_f64());
+            served.value = self.measurement.add(served.value, self.value);
+            served.dur += self.dur;
+            served.calls += 1;
+            served.end = Some(end);
+        }
+        served
+    }
+
+    /// Call `f` once with a single iteration on a new thread and return the
+    /// maximum depth of its stack in bytes. Returns `None` if the thread could
+    /// not be created or `f` panicked.
+    fn measure_stack(&self, f: fn(&mut Bencher)) -> Option<usize> {
+        // caches and counters would only add their own stack usage
+        let config = BenchmarkConfig {
+            cache_cold: false,
+            perf_events: Vec::new(),
+            profiler_control: None,
+            ..(*self.config).clone()
+        };
+        let seed = self.seed;
+
+        thread::scope(|scope| {
+            let thread = thread::Builder::new()
+                .name("smbench-stack".to_owned())
+                .stack_size(stack::STACK_SIZE)
+                .spawn_scoped(scope, move || {
+                    let mut bencher = Bencher::new(Arc::new(config));
+                    bencher.set_seed(seed);
+                    stack::high_water_mark(&mut || f(&mut bencher))
+                })
+                .ok()?;
+            thread.join().ok()
+        })
+    }
+
+    /// Run the benchmark in a loop for `seconds` without statistics while
+    /// `sampler` records backtraces
+    pub(crate) fn profile(
+        &mut self,
+        mut f: fn(&mut Bencher),
+        seconds: f64,
+        sampler: &mut Sampler,
+    ) -> Result<Profile, Error> {
+        f = black_box(f);
+
+        self.input_pool = None;
+        self.input_offset = 0;
+        self.iterations = 1;
+        self.measure_time = false;
+        f(self);
+
+        if !self.measure_time {
+            return Err(Error::InvalidBenchmarkFunction(
+                "Bencher::iter() method was not called.".to_string(),
+            ));
+        };
+
+        // grow the calls until they are long enough, which also warms up
+        loop {
+            let start = Instant::now();
+            f(self);
+            if start.elapsed() >= PROFILE_BATCH_TIME || self.iterations >= PROFILE_MAX_ITERS {
+                break;
+            }
+            self.iterations *= 2;
+        }
+
+        watchdog::set_phase("profiling");
+        let deadline = Instant::now() + Duration::from_secs_f64(seconds);
+        sampler.start();
+        while Instant::now() < deadline && !stopped() {
+            f(self);
+        }
+        let profile = sampler.stop();
+
+        check_stopped()?;
+        Ok(profile)
+    }
+
+    /// Enable the external profiler until the returned guard is dropped. The
+    /// profiler is not used any more once enabling it fails.
+    fn record_profile(&mut self) -> Option<Recording> {
+        match self.controller.as_mut()?.record() {
+            Ok(recording) => Some(recording),
+            Err(e) => {
+                eprintln!("Warning: failed to control the profiler: {}", e);
+                self.controller = None;
+                None
+            }
+        }
+    }
+
+    /// Warm up and return the measured time and the elapsed time including
+    /// untimed work per iteration in nanoseconds
+    fn warm_up(&mut self, how_long: Duration, f: fn(&mut Bencher)) -> Result<(f64, f64), Error> {
         let how_long = duration_ns(how_long);
         let mut total_iters = 0;
         let mut elapsed_time = 0;
+        let mut wall_time = 0;
         self.iterations = 1;
 
         // finish warmups if total_iters exceeds 1000000 times
-        while elapsed_time < how_long / 2 {
+        while wall_time < how_long / 2 {
+            check_stopped()?;
+
             f(self);
             total_iters += self.iterations;
             elapsed_time += duration_ns(self.dur);
+            wall_time += duration_ns(self.wall);
             self.iterations <<= 1;
         }
 
         elapsed_time = cmp::max(elapsed_time, 1);
+        wall_time = cmp::max(wall_time, 1);
 
-        let ns_per_iter = elapsed_time / total_iters as u64;
-        self.iterations = (how_long.saturating_sub(elapsed_time) / ns_per_iter) as usize;
+        // may be less than 1ns if the routine was optimized away
+        let ns_per_iter = wall_time as f64 / total_iters as f64;
+        self.iterations = (how_long.saturating_sub(wall_time) as f64 / ns_per_iter) as usize;
         black_box(f(self));
 
         // recalculate ns_per_iter
-        elapsed_time as f64 / total_iters as f64
+        Ok((
+            elapsed_time as f64 / total_iters as f64,
+            wall_time as f64 / total_iters as f64,
+        ))
     }
 
+    /// Warm up until the time per iteration of the last `STEADY_STATE_WINDOW`
+    /// batches becomes stable, or `max_time` has passed.
+    fn warm_up_steady_state(
+        &mut self,
+        max_time: Duration,
+        f: fn(&mut Bencher),
+    ) -> Result<(f64, f64), Error> {
+        let max_time = duration_ns(max_time);
+        let mut total_iters = 0;
+        let mut elapsed_time = 0;
+        let mut wall_time = 0;
+        let mut ns_per_iters = Vec::new();
+        self.iterations = 1;
+
+        while wall_time < max_time {
+            check_stopped()?;
+
+            f(self);
+            total_iters += self.iterations;
+            elapsed_time += duration_ns(self.dur);
+            wall_time += duration_ns(self.wall);
+
+            // batches too short to be timed precisely are not compared
+            if duration_ns(self.wall) < STEADY_STATE_BATCH_TIME {
+                self.iterations <<= 1;
+                continue;
+            }
+
+            ns_per_iters.push(duration_ns(self.dur) as f64 / self.iterations as f64);
+            if ns_per_iters.len() >= STEADY_STATE_WINDOW {
+                let window = &ns_per_iters[ns_per_iters.len() - STEADY_STATE_WINDOW..];
+                if stats::is_steady_state(window, STEADY_STATE_MAX_CV) {
+                    let ns_per_iter = window.iter().sum::<f64>() / window.len() as f64;
+                    let wall_per_iter = wall_time as f64 / total_iters as f64;
+                    return Ok((ns_per_iter, f64::max(wall_per_iter, ns_per_iter)));
+                }
+            }
+        }
+
+        Ok((
+            cmp::max(elapsed_time, 1) as f64 / total_iters as f64,
+            cmp::max(wall_time, 1) as f64 / total_iters as f64,
+        ))
+    }
+
+    /// Run the benchmark. `on_measurement_start` is called with the number of
+    /// samples and the estimated measurement time in seconds after warm-up.
     #[inline(never)]
-    pub(crate) fn auto_bench(&mut self, mut f: fn(&mut Bencher)) -> Result<BenchmarkResult, Error> {
+    pub(crate) fn auto_bench(
+        &mut self,
+        mut f: fn(&mut Bencher),
+        on_measurement_start: &dyn Fn(usize, f64),
+    ) -> Result<BenchmarkResult, Error> {
         f = black_box(f);
 
+        self.input_pool = None;
+        self.input_offset = 0;
         self.iterations = 1;
         self.measure_time = false;
         f(self);
@@ -81,31 +901,305 @@ impl Bencher {
             ));
         };
 
-        let ns_per_iter = self.warm_up(Duration::from_secs_f64(self.config.warmup_time), f);
+        // the call above is the very first call of the routine
+        let mut first_calls = Vec::with_capacity(self.config.cold_start);
+        if self.config.cold_start > 0 {
+            first_calls.push(self.value);
+            for _ in 1..self.config.cold_start {
+                f(self);
+                first_calls.push(self.value);
+            }
+        }
+
+        watchdog::set_phase("warm-up");
+        let warmup_time = Duration::from_secs_f64(self.warmup_time);
+        let (ns_per_iter, wall_ns_per_iter) = match self.config.warmup_mode {
+            WarmupMode::Fixed => self.warm_up(warmup_time, f)?,
+            WarmupMode::SteadyState => self.warm_up_steady_state(warmup_time, f)?,
+        };
+
+        // timers may report zero for routines optimized away
+        let mut ns_per_iter = f64::max(ns_per_iter, MIN_NS_PER_ITER);
+        let mut wall_ns_per_iter = f64::max(wall_ns_per_iter, ns_per_iter);
+
+        // single calls cost more than iterations of a loop
+        let latency = self.config.mode == BenchmarkMode::Latency;
+        let mut histogram = None;
+        if latency {
+            let start = Instant::now();
+            self.measure_calls(f, LATENCY_PROBE_CALLS, &mut Histogram::new());
+            let call_ns = duration_ns(start.elapsed()) as f64 / LATENCY_PROBE_CALLS as f64;
+            wall_ns_per_iter = f64::max(wall_ns_per_iter, call_ns);
+            ns_per_iter = wall_ns_per_iter;
+            histogram = Some(Histogram::new());
+        }
 
         // ensure that each iteration takes >100us
-        let d = cmp::max((100_000. / ns_per_iter) as usize + 1, 10);
-        let max_iters = self.config.measurement_time * 1e9 / ns_per_iter;
+        let mut d = cmp::max((100_000. / ns_per_iter) as usize + 1, 10);
+        let max_iters = self.measurement_time * 1e9 / wall_ns_per_iter;
 
-        // maximize n over `(d + 2 * d + ... + n * d) <= max_iters`
-        let n = cmp::max(
-            (((1.0 + 8.0 * max_iters / d as f64).sqrt() - 1.0) * 0.5) as usize,
-            1,
-        );
+        // evicting caches makes each iteration slow, so take at least 5 samples
+        // (`d + 2 * d + ... + 5 * d <= max_iters`) within the measurement time
+        if self.evictor.is_some() {
+            d = cmp::min(d, cmp::max((max_iters / 15.0) as usize, 1));
+        }
+
+        // open-loop mode takes a sample for each rate, samples of latency mode
+        // have the same number of calls, otherwise maximize n over
+        // `(d + 2 * d + ... + n * d) <= max_iters`
+        let open_loop = self.config.mode == BenchmarkMode::OpenLoop;
+        let sizes: Vec<usize> = if open_loop {
+            vec![1; self.config.rates.len()]
+        } else if latency {
+            let n = cmp::max((max_iters / d as f64) as usize, 1);
+            vec![d; n]
+        } else {
+            let n = cmp::max(
+                (((1.0 + 8.0 * max_iters / d as f64).sqrt() - 1.0) * 0.5) as usize,
+                1,
+            );
+            (1..=n).map(|i| i * d).collect()
+        };
+        let n = sizes.len();
+        let estimated_time = if open_loop {
+            n as f64 * self.measurement_time
+        } else {
+            sizes.iter().sum::<usize>() as f64 * ns_per_iter * 1e-9
+        };
+        on_measurement_start(n, estimated_time);
+
+        watchdog::set_phase("measurement");
         let mut measurements = Vec::with_capacity(n);
-        for k in (d..=d * n).step_by(d) {
+        let mut wall_times = Vec::new();
+        let mut counters: Vec<(String, Vec<f64>)> = match self.perf {
+            Some(ref perf) => perf
+                .names()
+                .iter()
+                .map(|name| (name.clone(), Vec::with_capacity(n)))
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut metrics: Vec<(String, Unit, Vec<f64>)> = Vec::new();
+        let mut rates = Vec::new();
+        let mut layouts = Vec::new();
+
+        // samples are assigned to the layouts in turn, so that every layout is
+        // measured across the whole measurement and drift affects them alike
+        let memory_layouts: Vec<MemoryLayout> = if self.config.randomize_layout {
+            let mut rng = Rng::new(mix(self.seed));
+            let count = cmp::max(n / SAMPLES_PER_LAYOUT, 1);
+            (0..count).map(|_| MemoryLayout::random(&mut rng)).collect()
+        } else {
+            Vec::new()
+        };
+
+        let recording = self.record_profile();
+        for (i, &k) in sizes.iter().enumerate() {
+            check_stopped()?;
+
             self.iterations = k;
-            f(self);
-            measurements.push((k, self.dur.as_secs_f64()));
+
+            let layout = if memory_layouts.is_empty() {
+                None
+            } else {
+                layouts.push(i % memory_layouts.len());
+                Some(&memory_layouts[i % memory_layouts.len()])
+            };
+
+            // drop values reported by warm-up and previous samples
+            self.reported_counters.clear();
+            self.reported_metrics.clear();
+
+            let mut run = |b: &mut Bencher| {
+                if open_loop {
+                    let rate = b.config.rates[i];
+                    rates.push(b.measure_rate(f, rate));
+                } else if let Some(ref mut histogram) = histogram {
+                    b.measure_calls(f, k, histogram);
+                } else {
+                    f(b);
+                }
+            };
+            // calls of open-loop mode are passed between threads by allocating
+            let allocations = memory::allocations().filter(|_| !open_loop);
+            match layout {
+                Some(layout) => layout.run(&mut || run(self)),
+                None => run(self),
+            }
+            if let (Some((count, bytes)), Some((count_end, bytes_end))) =
+                (allocations, memory::allocations())
+            {
+                self.add_counter(ALLOCATION_COUNTERS[0], count_end - count);
+                self.add_counter(ALLOCATION_COUNTERS[1], bytes_end - bytes);
+            }
+            measurements.push((self.iterations, self.value));
+            if self.record_wall_time {
+                wall_times.push(self.dur.as_secs_f64());
+            }
+            if let Some(ref mut perf) = self.perf {
+                for (counter, &value) in counters.iter_mut().zip(perf.read()) {
+                    counter.1.push(value as f64);
+                }
+            }
+            for (name, value) in self.reported_counters.drain(..) {
+                let values = match counters.iter().position(|c| c.0 == name) {
+                    Some(idx) => &mut counters[idx].1,
+                    None => {
+                        counters.push((name, Vec::with_capacity(n)));
+                        &mut counters.last_mut().unwrap().1
+                    }
+                };
+                values.resize(i + 1, 0.0);
+                values[i] += value;
+            }
+            // counters not reported by this sample count zero
+            for counter in counters.iter_mut() {
+                counter.1.resize(i + 1, 0.0);
+            }
+            for (name, unit, value) in self.reported_metrics.drain(..) {
+                match metrics.iter_mut().find(|m| m.0 == name) {
+                    Some(metric) => metric.2.push(value),
+                    None => metrics.push((name, unit, vec![value])),
+                }
+            }
+
+            // higher rates would only build up longer queues
+            if rates.last().is_some_and(RateResult::is_saturated) {
+                break;
+            }
         }
+        drop(recording);
 
         Ok(BenchmarkResult {
             measurements,
+            wall_times,
+            counters,
+            metrics,
+            runs: Vec::new(),
+            layouts,
+            first_calls,
+            input_pool: self.input_pool,
+            latency: histogram,
+            rates,
+            stack: if self.config.measure_stack {
+                self.measure_stack(f)
+            } else {
+                None
+            },
+            empty_loop: None,
         })
     }
 }
 
+/// Whether the benchmark should stop early, because Ctrl-C was pressed or the
+/// benchmark timed out
+#[inline]
+fn stopped() -> bool {
+    signal::interrupted() || watchdog::cancelled()
+}
+
+/// Fail if the benchmark should stop early
+fn check_stopped() -> Result<(), Error> {
+    if signal::interrupted() {
+        return Err(Error::Interrupted);
+    }
+    watchdog::check()
+}
+
+/// Sleep until shortly before `deadline` and spin for the rest, which is more
+/// precise than sleeping alone. Spinning yields to other threads, so that
+/// workers sharing the CPU with the scheduler are not delayed.
+fn wait_until(deadline: Instant) {
+    let now = Instant::now();
+    if deadline > now + SPIN_TIME {
+        thread::sleep(deadline - now - SPIN_TIME);
+    }
+    while Instant::now() < deadline {
+        thread::yield_now();
+    }
+}
+
+/// Names of counters and metrics are written to the result lines of
+/// `RawReporter`, so they must not contain separators
+fn check_metric_name(name: &str) {
+    assert!(
+        !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '='),
+        "invalid metric name: {:?}",
+        name
+    );
+}
+
 #[inline]
 fn duration_ns(dur: Duration) -> u64 {
     dur.as_secs() * 1_000_000_000 + dur.subsec_nanos() as u64
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Distribution;
+    use crate::summary;
+    use assert_float_eq::*;
+
+    fn result(samples: usize, counters: &[&str]) -> BenchmarkResult {
+        BenchmarkResult {
+            measurements: (1..=samples).map(|i| (i, i as f64 * 1e-6)).collect(),
+            wall_times: Vec::new(),
+            counters: counters
+                .iter()
+                .map(|&name| (name.to_owned(), vec![1.0; samples]))
+                .collect(),
+            metrics: Vec::new(),
+            runs: Vec::new(),
+            layouts: Vec::new(),
+            first_calls: Vec::new(),
+            input_pool: None,
+            latency: None,
+            rates: Vec::new(),
+            stack: None,
+            empty_loop: None,
+        }
+    }
+
+    #[test]
+    fn merge_counters() {
+        let merged = BenchmarkResult::merge(vec![result(2, &["a"]), result(3, &["b"])]);
+        assert_eq!(merged.runs, vec![2, 3]);
+        assert_eq!(
+            merged.counters,
+            vec![
+                ("a".to_owned(), vec![1.0, 1.0, 0.0, 0.0, 0.0]),
+                ("b".to_owned(), vec![0.0, 0.0, 1.0, 1.0, 1.0]),
+            ]
+        );
+    }
+
+    fn reporting(b: &mut Bencher) {
+        b.iter(|| ());
+        let iterations = b.iterations as u64;
+        b.report_counter("items", iterations);
+        b.report_counter("items", 2 * iterations);
+        b.report_metric("ratio", 0.25, Unit::Ratio);
+        b.report_metric("ratio", 0.5, Unit::Ratio);
+    }
+
+    #[test]
+    fn reported_counters_and_metrics() {
+        let mut bencher = Bencher::new(Arc::new(BenchmarkConfig::default()));
+        bencher.set_time(0.01, 0.05);
+        let result = bencher.auto_bench(reporting, &|_, _| {}).unwrap();
+
+        // counters add up within a sample, metrics are replaced
+        let samples = result.measurements.len();
+        assert_eq!(result.counters.len(), 1);
+        assert_eq!(result.counters[0].1.len(), samples);
+        assert_eq!(result.metrics.len(), 1);
+        assert_eq!(result.metrics[0].2, vec![0.5; samples]);
+
+        let summ = summary::summarize(&result, false);
+        assert_eq!(summ.counters[0].0, "items");
+        assert_float_absolute_eq!(summ.counters[0].1.mean(), 3.0, 1e-9);
+        assert_eq!(summ.metrics[0].1, Unit::Ratio);
+        assert_float_absolute_eq!(summ.metrics[0].2.mean(), 0.5, 1e-9);
+    }
+}
@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use crate::config::BenchmarkConfig;
+
+/// Minimum time given to each benchmark even if the budget is exhausted
+const MIN_TIME_PER_BENCHMARK: f64 = 0.05;
+
+/// Time budget of the whole suite given by `--total-time`.
+///
+/// The remaining time is divided equally among the benchmarks which have not
+/// started yet, so the time saved by benchmarks finishing early is given to
+/// the following benchmarks.
+pub(crate) struct Budget {
+    deadline: Instant,
+    /// number of benchmarks which have not started yet
+    remaining: usize,
+    /// fraction of the time of each benchmark used for warm-up
+    warmup_ratio: f64,
+}
+
+impl Budget {
+    /// Divide `total_time` seconds among `benchmarks` benchmarks
+    pub fn new(config: &BenchmarkConfig, total_time: f64, benchmarks: usize) -> Self {
+        Budget {
+            deadline: Instant::now() + Duration::from_secs_f64(total_time),
+            remaining: benchmarks,
+            warmup_ratio: config.warmup_time / (config.warmup_time + config.measurement_time),
+        }
+    }
+
+    /// Return the plan of the next benchmark without consuming it
+    pub fn peek(&self) -> (f64, f64) {
+        let remaining_time = self
+            .deadline
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64();
+        let time = f64::max(
+            remaining_time / self.remaining.max(1) as f64,
+            MIN_TIME_PER_BENCHMARK,
+        );
+
+        (time * self.warmup_ratio, time * (1.0 - self.warmup_ratio))
+    }
+
+    /// Plan the warm-up time and the measurement time (in seconds) of the next
+    /// benchmark
+    pub fn next(&mut self) -> (f64, f64) {
+        let plan = self.peek();
+        self.remaining = self.remaining.saturating_sub(1);
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_float_eq::*;
+
+    #[test]
+    fn replan() {
+        let config = BenchmarkConfig {
+            warmup_time: 1.0,
+            measurement_time: 3.0,
+            ..BenchmarkConfig::default()
+        };
+        let mut budget = Budget::new(&config, 12.0, 4);
+
+        let (warmup_time, measurement_time) = budget.next();
+        assert_float_absolute_eq!(warmup_time, 0.75, 1e-3);
+        assert_float_absolute_eq!(measurement_time, 2.25, 1e-3);
+
+        // the time left by the first benchmark is given to the other three
+        let (warmup_time, measurement_time) = budget.next();
+        assert_float_absolute_eq!(warmup_time + measurement_time, 4.0, 1e-3);
+        assert_float_absolute_eq!(warmup_time / measurement_time, 1.0 / 3.0, 1e-6);
+
+        // benchmarks get the minimum time once the budget is exhausted
+        let mut budget = Budget::new(&config, 0.0, 1);
+        let (warmup_time, measurement_time) = budget.next();
+        assert_float_absolute_eq!(warmup_time + measurement_time, MIN_TIME_PER_BENCHMARK);
+    }
+}
@@ -0,0 +1,96 @@
+use std::ptr;
+
+use crate::common::black_box;
+
+/// Cache size assumed if the size of the last level cache cannot be detected
+const DEFAULT_CACHE_SIZE: usize = 32 * 1024 * 1024;
+const CACHE_LINE_SIZE: usize = 64;
+
+/// Evicts the CPU caches by walking a buffer larger than the last level cache
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CacheEvictor {
+    buffer: Vec<u8>,
+}
+
+impl CacheEvictor {
+    pub fn new() -> Self {
+        let size = last_level_cache_size().unwrap_or(DEFAULT_CACHE_SIZE);
+        Self {
+            buffer: vec![0; size * 2],
+        }
+    }
+
+    /// Write every cache line of the buffer so that data used by the
+    /// benchmark is evicted from all levels of the cache
+    pub fn evict(&mut self) {
+        for i in (0..self.buffer.len()).step_by(CACHE_LINE_SIZE) {
+            unsafe {
+                let p = self.buffer.as_mut_ptr().add(i);
+                ptr::write_volatile(p, ptr::read_volatile(p).wrapping_add(1));
+            }
+        }
+        black_box(&mut self.buffer);
+    }
+}
+
+/// Size of the last level cache in bytes, read from sysfs
+#[cfg(target_os = "linux")]
+fn last_level_cache_size() -> Option<usize> {
+    use std::fs;
+
+    let mut last_level = None;
+    for entry in fs::read_dir("/sys/devices/system/cpu/cpu0/cache").ok()? {
+        let path = entry.ok()?.path();
+        if !path.file_name()?.to_str()?.starts_with("index") {
+            continue;
+        }
+
+        let level: u32 = match fs::read_to_string(path.join("level")) {
+            Ok(s) => s.trim().parse().ok()?,
+            Err(_) => continue,
+        };
+        let size = match fs::read_to_string(path.join("size")) {
+            Ok(s) => parse_cache_size(&s)?,
+            Err(_) => continue,
+        };
+
+        // levels start at 1
+        if level > last_level.map_or(0, |(l, _)| l) {
+            last_level = Some((level, size));
+        }
+    }
+
+    last_level.map(|(_, size)| size)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn last_level_cache_size() -> Option<usize> {
+    None
+}
+
+/// Parse cache size in sysfs format (e.g. `32K`, `16384K`, `8M`)
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_cache_size(s: &str) -> Option<usize> {
+    let s = s.trim();
+    let (value, scale) = match s.as_bytes().last()? {
+        b'K' => (&s[..s.len() - 1], 1024),
+        b'M' => (&s[..s.len() - 1], 1024 * 1024),
+        b'G' => (&s[..s.len() - 1], 1024 * 1024 * 1024),
+        _ => (s, 1),
+    };
+
+    value.parse::<usize>().ok().map(|v| v * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_size() {
+        assert_eq!(parse_cache_size("32K\n"), Some(32 * 1024));
+        assert_eq!(parse_cache_size("8M"), Some(8 * 1024 * 1024));
+        assert_eq!(parse_cache_size("512"), Some(512));
+        assert_eq!(parse_cache_size("K"), None);
+    }
+}
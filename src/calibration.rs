@@ -0,0 +1,108 @@
+use std::cmp;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::bench::Bencher;
+use crate::common::black_box;
+use crate::config::{BenchmarkConfig, BenchmarkMode, WarmupMode};
+use crate::stats::Normal;
+use crate::summary;
+
+/// Warm-up time and measurement time of the empty loop in seconds
+const EMPTY_LOOP_WARMUP_TIME: f64 = 0.05;
+const EMPTY_LOOP_MEASUREMENT_TIME: f64 = 0.2;
+
+/// Maximum number of samples and time spent measuring the timer
+const TIMER_SAMPLES: usize = 1000;
+const TIMER_CALIBRATION_TIME: Duration = Duration::from_millis(20);
+
+/// CPU frequency assumed if the maximum frequency cannot be detected
+const DEFAULT_CPU_FREQUENCY: f64 = 4e9;
+
+/// Resolution and cost of `Instant`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Timer {
+    /// smallest non-zero difference between two readings in seconds
+    pub resolution: f64,
+    /// time of a pair of `Instant::now()` calls in seconds
+    pub overhead: f64,
+}
+
+/// Measure the resolution and the overhead of the timer
+pub(crate) fn timer() -> Timer {
+    let deadline = Instant::now() + TIMER_CALIBRATION_TIME;
+
+    let mut resolution = Duration::MAX;
+    for _ in 0..TIMER_SAMPLES {
+        let start = Instant::now();
+        let mut end = Instant::now();
+        while end == start {
+            end = Instant::now();
+        }
+        resolution = cmp::min(resolution, end - start);
+
+        if end >= deadline {
+            break;
+        }
+    }
+
+    let start = Instant::now();
+    for _ in 0..TIMER_SAMPLES {
+        black_box(Instant::now().elapsed());
+    }
+    let overhead = start.elapsed().as_secs_f64() / TIMER_SAMPLES as f64;
+
+    Timer {
+        resolution: resolution.as_secs_f64(),
+        overhead,
+    }
+}
+
+fn empty(b: &mut Bencher) {
+    b.iter(|| ());
+}
+
+/// Measure the time per iteration of `Bencher::iter` with an empty routine,
+/// which is the smallest time the framework can tell apart from zero.
+///
+/// Returns `None` if the measurement was interrupted.
+pub(crate) fn empty_loop(config: &BenchmarkConfig) -> Option<Normal> {
+    let config = BenchmarkConfig {
+        warmup_mode: WarmupMode::Fixed,
+        mode: BenchmarkMode::Throughput,
+        rates: Vec::new(),
+        randomize_layout: false,
+        cold_start: 0,
+        cache_cold: false,
+        measure_stack: false,
+        perf_events: Vec::new(),
+        profiler_control: None,
+        ..config.clone()
+    };
+
+    let mut bencher = Bencher::new(Arc::new(config));
+    bencher.set_time(EMPTY_LOOP_WARMUP_TIME, EMPTY_LOOP_MEASUREMENT_TIME);
+    let result = bencher.auto_bench(empty, &|_, _| {}).ok()?;
+
+    summary::summarize(&result, false).elapsed_time
+}
+
+/// Duration of one CPU cycle at the maximum frequency in seconds
+pub(crate) fn cycle_time() -> f64 {
+    static CYCLE_TIME: OnceLock<f64> = OnceLock::new();
+    *CYCLE_TIME.get_or_init(|| 1.0 / max_cpu_frequency().unwrap_or(DEFAULT_CPU_FREQUENCY))
+}
+
+/// Maximum CPU frequency in Hz, read from sysfs
+#[cfg(target_os = "linux")]
+fn max_cpu_frequency() -> Option<f64> {
+    let content =
+        std::fs::read_to_string("/sys/devices/system/cpu/cpu0/cpufreq/cpuinfo_max_freq").ok()?;
+    let khz: f64 = content.trim().parse().ok()?;
+    Some(khz * 1e3)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn max_cpu_frequency() -> Option<f64> {
+    None
+}
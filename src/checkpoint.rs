@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::bench::BenchmarkResult;
+use crate::common::create_output_dir;
+use crate::config::BenchmarkConfig;
+use crate::reporter::{format_result_line, parse_raw_line, RawEvent};
+
+const HEADER_PREFIX: &str = "smbench:checkpoint";
+
+/// Results of completed benchmarks saved to the output directory with
+/// `--resume`, so that an interrupted run can be resumed by running it again
+/// with `--resume`.
+///
+/// The checkpoint file starts with a header line which identifies the
+/// benchmark executable and the configuration, followed by one
+/// `RawReporter`-style result line per completed benchmark.
+pub(crate) struct Checkpoint {
+    /// `None` in child processes, where the parent process writes the results
+    file: Option<File>,
+    results: HashMap<(String, String), BenchmarkResult>,
+    processes: usize,
+}
+
+impl Checkpoint {
+    /// Open the checkpoint file of the current executable (only used with
+    /// `--resume`) and load the results saved by a previous run with the same
+    /// executable and configuration.
+    ///
+    /// Returns `None` if the output directory is not available.
+    pub fn open(config: &BenchmarkConfig) -> Option<Self> {
+        Self::open_path(&checkpoint_path()?, config)
+    }
+
+    fn open_path(path: &Path, config: &BenchmarkConfig) -> Option<Self> {
+        let header = format!("{}\t{}", HEADER_PREFIX, fingerprint(config));
+
+        let mut results = HashMap::new();
+        if let Ok(content) = fs::read_to_string(path) {
+            let mut lines = content.lines();
+            if lines.next() == Some(&*header) {
+                for line in lines {
+                    if let Some(RawEvent::Complete {
+                        group,
+                        name,
+                        result: Ok(result),
+                    }) = parse_raw_line(line)
+                    {
+                        // the parent process has already dropped incomplete
+                        // results when it rewrote the file
+                        if config.child || is_complete(&result, config.processes) {
+                            results.insert((group, name), result);
+                        }
+                    }
+                }
+            } else if !config.child {
+                eprintln!(
+                    "Warning: ignoring checkpoint {} saved with a different \
+                    executable or configuration",
+                    path.display()
+                );
+            }
+        }
+
+        let mut checkpoint = Checkpoint {
+            file: None,
+            results,
+            processes: config.processes,
+        };
+
+        if config.child {
+            return Some(checkpoint);
+        }
+
+        eprintln!(
+            "Resuming from checkpoint: {} benchmark(s) already completed",
+            checkpoint.results.len()
+        );
+
+        // rewrite the file so that stale or partially written lines are dropped
+        let mut file = File::create(path).ok()?;
+        writeln!(file, "{}", header).ok()?;
+        for ((group, name), result) in checkpoint.results.iter() {
+            writeln!(file, "{}", format_result_line(group, name, result)).ok()?;
+        }
+        file.flush().ok()?;
+        checkpoint.file = Some(file);
+
+        Some(checkpoint)
+    }
+
+    /// Whether the result of the benchmark is saved
+    pub fn contains(&self, group: &str, name: &str) -> bool {
+        self.results
+            .contains_key(&(group.to_owned(), name.to_owned()))
+    }
+
+    /// Take the saved result of the benchmark
+    pub fn take(&mut self, group: &str, name: &str) -> Option<BenchmarkResult> {
+        self.results.remove(&(group.to_owned(), name.to_owned()))
+    }
+
+    /// Save the result of the completed benchmark. Results measured by fewer
+    /// processes than requested (e.g. interrupted by Ctrl-C) are not saved.
+    pub fn append(&mut self, group: &str, name: &str, result: &BenchmarkResult) {
+        if !is_complete(result, self.processes) {
+            return;
+        }
+
+        if let Some(ref mut file) = self.file {
+            let _ = writeln!(file, "{}", format_result_line(group, name, result));
+            let _ = file.flush();
+        }
+    }
+}
+
+fn checkpoint_path() -> Option<PathBuf> {
+    let exe = env::current_exe().ok()?;
+    let mut path = create_output_dir()?;
+    path.push(exe.file_stem()?);
+    path.set_extension("checkpoint");
+    Some(path)
+}
+
+/// Identify the executable and every option which affects the results. The
+/// number of processes is checked per result by `is_complete`.
+fn fingerprint(config: &BenchmarkConfig) -> String {
+    let modified = env::current_exe()
+        .and_then(fs::metadata)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+
+    format!(
+        "exe={} warm-up={} warm-up-mode={:?} measurement={} total-time={} \
+        randomize-layout={} cold-start={} isolate={} cache-cold={} seed={} \
+        measure={:?} mode={:?} rates={:?} workers={} perf-events={} measure-stack={} \
+        subtract-overhead={}",
+        modified,
+        config.warmup_time,
+        config.warmup_mode,
+        config.measurement_time,
+        config.total_time,
+        config.randomize_layout,
+        config.cold_start,
+        config.isolate,
+        config.cache_cold,
+        config.seed,
+        config.measure,
+        config.mode,
+        config.rates,
+        config.workers,
+        config.perf_events.join(","),
+        config.measure_stack,
+        config.subtract_overhead
+    )
+}
+
+fn is_complete(result: &BenchmarkResult, processes: usize) -> bool {
+    let runs = if result.runs.is_empty() {
+        1
+    } else {
+        result.runs.len()
+    };
+    !result.measurements.is_empty() && runs == processes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(measurements: Vec<(usize, f64)>) -> BenchmarkResult {
+        BenchmarkResult {
+            measurements,
+            wall_times: Vec::new(),
+            counters: Vec::new(),
+            metrics: Vec::new(),
+            runs: Vec::new(),
+            layouts: Vec::new(),
+            first_calls: Vec::new(),
+            input_pool: None,
+            latency: None,
+            rates: Vec::new(),
+            stack: None,
+            empty_loop: None,
+        }
+    }
+
+    #[test]
+    fn append_and_reload() {
+        let path = env::temp_dir().join(format!("smbench-{}.checkpoint", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = BenchmarkConfig {
+            resume: true,
+            ..BenchmarkConfig::default()
+        };
+
+        let mut checkpoint = Checkpoint::open_path(&path, &config).unwrap();
+        assert!(!checkpoint.contains("group", "a"));
+        checkpoint.append("group", "a", &result(vec![(10, 1.5e-6)]));
+        // incomplete results are not saved
+        checkpoint.append("group", "b", &result(Vec::new()));
+        drop(checkpoint);
+
+        let mut checkpoint = Checkpoint::open_path(&path, &config).unwrap();
+        assert!(!checkpoint.contains("group", "b"));
+        assert_eq!(
+            checkpoint.take("group", "a"),
+            Some(result(vec![(10, 1.5e-6)]))
+        );
+        drop(checkpoint);
+
+        // results measured differently are discarded
+        let config = BenchmarkConfig {
+            cold_start: 3,
+            ..config
+        };
+        let checkpoint = Checkpoint::open_path(&path, &config).unwrap();
+        assert!(!checkpoint.contains("group", "a"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
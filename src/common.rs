@@ -76,13 +76,12 @@ pub fn black_box<T>(dummy: T) -> T {
     }
 }
 
-#[cfg(feature = "json")]
 pub(crate) fn create_output_dir() -> Option<std::path::PathBuf> {
     use std::path::PathBuf;
     use std::process::Command;
 
     let output = Command::new("cargo")
-        .args(&["metadata", "--format-version", "1"])
+        .args(["metadata", "--format-version", "1"])
         .output()
         .ok()?;
 
@@ -1,16 +1,203 @@
 #[cfg(feature = "argparse")]
 use argparse::{
     action::{IFlagAction, ParseResult},
-    ArgumentParser, Store,
+    ArgumentParser, Store, StoreTrue,
 };
 
-#[derive(Debug, PartialEq)]
+use std::path::PathBuf;
+use std::str::FromStr;
+#[cfg(feature = "argparse")]
+use std::io::{self, Write};
+#[cfg(feature = "argparse")]
+use std::{env, process};
+
+#[cfg(feature = "argparse")]
+use crate::measurement::{self, PerfCounters};
+
+/// How to decide the end of warm-up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WarmupMode {
+    /// Warm up for `warmup_time`
+    Fixed,
+    /// Warm up until the time per iteration becomes stable, but at most for
+    /// `warmup_time`
+    SteadyState,
+}
+
+impl FromStr for WarmupMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(WarmupMode::Fixed),
+            "steady-state" => Ok(WarmupMode::SteadyState),
+            _ => Err(format!("invalid warm-up mode: {}", s)),
+        }
+    }
+}
+
+/// What benchmarks measure
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeasurementKind {
+    /// Wall-clock time
+    WallTime,
+    /// CPU time of the thread running the benchmark
+    ThreadCpuTime,
+    /// CPU time of all threads of the process
+    ProcessCpuTime,
+    /// Reference cycles counted by the time stamp counter (x86_64 only)
+    Cycles,
+}
+
+impl FromStr for MeasurementKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "time" => Ok(MeasurementKind::WallTime),
+            "cpu-time" => Ok(MeasurementKind::ThreadCpuTime),
+            "process-cpu-time" => Ok(MeasurementKind::ProcessCpuTime),
+            "cycles" => Ok(MeasurementKind::Cycles),
+            _ => Err(format!("invalid measurement: {}", s)),
+        }
+    }
+}
+
+/// How samples are taken
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BenchmarkMode {
+    /// Time batches of a growing number of iterations and estimate the mean
+    /// time per iteration
+    Throughput,
+    /// Time every call of the benchmark function with a single iteration and
+    /// record the distribution of the times
+    Latency,
+    /// Call the benchmark function at the fixed rates given by `rates` and
+    /// record the latency of every call from its intended start time
+    OpenLoop,
+}
+
+impl FromStr for BenchmarkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "throughput" => Ok(BenchmarkMode::Throughput),
+            "latency" => Ok(BenchmarkMode::Latency),
+            "open-loop" => Ok(BenchmarkMode::OpenLoop),
+            _ => Err(format!("invalid mode: {}", s)),
+        }
+    }
+}
+
+/// Where an external profiler (e.g. `perf record --control`) reads `enable`
+/// and `disable` commands, and optionally acknowledges each command
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProfilerControl {
+    /// Named pipes created by the profiler (`fifo:CTL[,ACK]`)
+    Fifo {
+        control: PathBuf,
+        ack: Option<PathBuf>,
+    },
+    /// File descriptors inherited from the profiler (`fd:CTL[,ACK]`)
+    Fd { control: i32, ack: Option<i32> },
+}
+
+impl FromStr for ProfilerControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid profiler control: {}", s);
+
+        let idx = s.find(':').ok_or_else(invalid)?;
+        let mut targets = s[idx + 1..].split(',');
+        let control = targets.next().filter(|t| !t.is_empty()).ok_or_else(invalid)?;
+        let ack = targets.next();
+        if targets.next().is_some() {
+            return Err(invalid());
+        }
+
+        match &s[..idx] {
+            "fifo" => Ok(ProfilerControl::Fifo {
+                control: PathBuf::from(control),
+                ack: ack.map(PathBuf::from),
+            }),
+            "fd" => {
+                let parse_fd = |t: &str| t.parse().ok().filter(|&fd: &i32| fd >= 0);
+                Ok(ProfilerControl::Fd {
+                    control: parse_fd(control).ok_or_else(invalid)?,
+                    ack: match ack {
+                        Some(ack) => Some(parse_fd(ack).ok_or_else(invalid)?),
+                        None => None,
+                    },
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct BenchmarkConfig {
     pub filter: String,
     pub warmup_time: f64,
+    pub warmup_mode: WarmupMode,
     pub measurement_time: f64,
     pub confidence_level: f64,
+    pub processes: usize,
+    pub randomize_layout: bool,
+    /// number of first calls of each benchmark measured separately before
+    /// warm-up (0 means not measured)
+    pub cold_start: usize,
+    /// run each benchmark in its own child process
+    pub isolate: bool,
+    /// evict CPU caches before every sample
+    pub cache_cold: bool,
+    /// measure the maximum stack depth of the benchmark function
+    pub measure_stack: bool,
+    /// run the selected benchmarks under a sampling profiler for this many
+    /// seconds instead of measuring them (0 means not profiled)
+    pub profile_time: f64,
+    /// seed of the random number generator returned by `Bencher::rng()`
+    pub seed: u64,
+    /// what benchmarks measure
+    pub measure: MeasurementKind,
+    /// whether the mean or the distribution of single calls is measured
+    pub mode: BenchmarkMode,
+    /// calls per second offered in open-loop mode, swept in order until the
+    /// benchmark saturates
+    pub rates: Vec<f64>,
+    /// number of threads executing the calls of open-loop mode, which is the
+    /// maximum number of calls in flight
+    pub workers: usize,
+    /// performance counters counted around each sample (Linux only)
+    pub perf_events: Vec<String>,
+    /// external profiler enabled only while benchmarks are measured
+    pub profiler_control: Option<ProfilerControl>,
+    /// subtract the time of an empty loop from the time of every benchmark
+    pub subtract_overhead: bool,
+    /// time limit of each benchmark in seconds (0 means no limit)
+    pub timeout: f64,
+    /// time budget of the whole suite in seconds (0 means fixed warm-up and
+    /// measurement time for each benchmark)
+    pub total_time: f64,
+    /// save results to the checkpoint file and skip benchmarks whose results
+    /// were saved there
+    pub resume: bool,
+    /// run only the K-th of N shards of the benchmark suite (`K` starts from 1)
+    pub shard: Option<(usize, usize)>,
+    /// JSON reports to merge instead of running benchmarks
+    pub merge: Vec<String>,
     pub(crate) reporters_string: String,
+    pub(crate) child: bool,
+    /// number of selected benchmarks to skip (used to resume child processes)
+    pub(crate) skip: usize,
+    /// mean and standard error of the empty loop measured by the parent
+    /// process (see `calibration::empty_loop`)
+    pub(crate) empty_loop: Option<(f64, f64)>,
+    /// resolution and overhead of the timer measured by the parent process
+    /// (see `calibration::timer`)
+    pub(crate) timer: Option<(f64, f64)>,
 }
 
 impl BenchmarkConfig {
@@ -24,6 +211,14 @@ impl BenchmarkConfig {
         }
 
         let mut config = BenchmarkConfig::default();
+        let mut shard_string = String::new();
+        let mut merge_string = String::new();
+        let mut perf_events_string = String::new();
+        let mut rates_string = String::new();
+        let mut profiler_control_string = String::new();
+        let mut total_time_string = String::new();
+        let mut empty_loop_string = String::new();
+        let mut timer_string = String::new();
 
         let mut ap = ArgumentParser::new();
         ap.set_description("SMBench Executable");
@@ -35,6 +230,11 @@ impl BenchmarkConfig {
             Store,
             "Specify the maximum warm up time in seconds [default is 2.0]",
         );
+        ap.refer(&mut config.warmup_mode).metavar("MODE").add_option(
+            &["--warm-up"],
+            Store,
+            "End warm-up after fixed time or at steady state [fixed|steady-state]",
+        );
         ap.refer(&mut config.measurement_time).add_option(
             &["--measurement-time"],
             Store,
@@ -50,10 +250,149 @@ impl BenchmarkConfig {
             Store,
             "Specify reporters to use.",
         );
+        ap.refer(&mut config.processes).metavar("N").add_option(
+            &["--processes"],
+            Store,
+            "Run benchmarks in N fresh child processes and merge the results [default is 1]",
+        );
+        ap.refer(&mut config.randomize_layout).add_option(
+            &["--randomize-layout"],
+            StoreTrue,
+            "Randomly shift the stack and pad heap allocations between samples",
+        );
+        ap.refer(&mut config.cold_start).metavar("K").add_option(
+            &["--cold-start"],
+            Store,
+            "Measure the first K calls of each benchmark before warm-up [default is 0]",
+        );
+        ap.refer(&mut config.isolate).add_option(
+            &["--isolate"],
+            StoreTrue,
+            "Run each benchmark in its own fresh child process",
+        );
+        ap.refer(&mut config.cache_cold).add_option(
+            &["--cache-cold"],
+            StoreTrue,
+            "Evict CPU caches before every iteration, which is then timed separately",
+        );
+        ap.refer(&mut config.measure_stack).add_option(
+            &["--measure-stack"],
+            StoreTrue,
+            "Measure the maximum stack depth of one call of each benchmark on a thread \
+            with a painted stack (heap usage is not reported, see smbench_trace_memory!() \
+            for counting allocations)",
+        );
+        ap.refer(&mut config.profile_time).metavar("SECONDS").add_option(
+            &["--profile-time"],
+            Store,
+            "Profile each selected benchmark for SECONDS instead of measuring it and \
+            write its sampled stacks in the collapsed format of flame graphs. Build with \
+            RUSTFLAGS=\"-C force-frame-pointers=yes\" to sample complete stacks",
+        );
+        ap.refer(&mut config.seed).metavar("N").add_option(
+            &["--seed"],
+            Store,
+            "Seed of the random number generator of Bencher::rng() [default is 0]",
+        );
+        ap.refer(&mut config.measure).metavar("KIND").add_option(
+            &["--measure"],
+            Store,
+            "What benchmarks measure [time|cpu-time|process-cpu-time|cycles]",
+        );
+        ap.refer(&mut config.mode).metavar("MODE").add_option(
+            &["--mode"],
+            Store,
+            "Measure the mean time per iteration, the latency percentiles of single \
+            calls, or the latency of calls issued at fixed rates \
+            [throughput|latency|open-loop]",
+        );
+        ap.refer(&mut rates_string).metavar("RATE[,RATE..]").add_option(
+            &["--rate"],
+            Store,
+            "Calls per second offered in open-loop mode. Several rates are swept in \
+            order until the benchmark cannot keep up",
+        );
+        ap.refer(&mut config.workers).metavar("N").add_option(
+            &["--workers"],
+            Store,
+            "Number of threads executing the calls of open-loop mode, which bounds \
+            the calls in flight [default is 1]",
+        );
+        ap.refer(&mut perf_events_string).metavar("EVENT[,EVENT..]").add_option(
+            &["--perf-events"],
+            Store,
+            "Count perf events around each sample (e.g. instructions,cycles,branch-misses,\
+            cache-misses,task-clock,page-faults,context-switches)",
+        );
+        ap.refer(&mut profiler_control_string).metavar("fifo:CTL[,ACK]|fd:CTL[,ACK]").add_option(
+            &["--profiler-control"],
+            Store,
+            "Write enable/disable commands around the measurement of each benchmark \
+            to the control FIFO or file descriptor of an external profiler (e.g. \
+            perf record --control) and wait for acknowledgements on ACK",
+        );
+        ap.refer(&mut config.subtract_overhead).add_option(
+            &["--subtract-overhead"],
+            StoreTrue,
+            "Subtract the time per iteration of an empty loop from every benchmark",
+        );
+        ap.refer(&mut config.timeout).metavar("SECONDS").add_option(
+            &["--timeout"],
+            Store,
+            "Fail a benchmark if it does not finish within SECONDS [default is no limit]. \
+            Without child processes, a benchmark stuck in a single call exits the process \
+            one second after the limit without finishing the reports (see --isolate)",
+        );
+        ap.refer(&mut total_time_string).metavar("TIME").add_option(
+            &["--total-time"],
+            Store,
+            "Divide TIME (e.g. 90s, 10m) among the selected benchmarks instead of \
+            using fixed warm-up and measurement time",
+        );
+        ap.refer(&mut config.resume).add_option(
+            &["--resume"],
+            StoreTrue,
+            "Save completed benchmarks to a checkpoint file, and skip benchmarks \
+            already saved by the previous (interrupted) run with --resume",
+        );
+        ap.refer(&mut shard_string).metavar("K/N").add_option(
+            &["--shard"],
+            Store,
+            "Run only the K-th of N disjoint subsets of benchmarks",
+        );
+        ap.refer(&mut merge_string).metavar("FILE[,FILE..]").add_option(
+            &["--merge"],
+            Store,
+            "Merge JSON reports of shards instead of running benchmarks",
+        );
+        ap.refer(&mut config.child)
+            .add_option(&["--smbench-child"], StoreTrue, "");
+        ap.refer(&mut config.skip)
+            .add_option(&["--smbench-skip"], Store, "");
+        ap.refer(&mut empty_loop_string)
+            .add_option(&["--smbench-empty-loop"], Store, "");
+        ap.refer(&mut timer_string)
+            .add_option(&["--smbench-timer"], Store, "");
 
         ap.parse_args_or_exit();
+
+        // errors found after parsing are printed like those of argparse
+        let program = env::args().next().unwrap_or_default();
+        let mut usage = Vec::new();
+        ap.print_usage(&program, &mut usage).unwrap();
         drop(ap);
 
+        let exit_with_error = |message: &str| -> ! {
+            let mut stderr = io::stderr();
+            stderr.write_all(&usage).ok();
+            writeln!(stderr, "{}: {}", program, message).ok();
+            process::exit(2);
+        };
+
+        if let Err(e) = measurement::check_supported(config.measure) {
+            exit_with_error(&e);
+        }
+
         // varidate arguments
         if config.confidence_level < 0.0 || 1.0 < config.confidence_level {
             panic!(
@@ -63,6 +402,108 @@ impl BenchmarkConfig {
             );
         }
 
+        if config.timeout < 0.0 {
+            panic!(
+                "invalid timeout: {}. timeout must be positive",
+                config.timeout
+            );
+        }
+
+        if config.profile_time < 0.0 {
+            panic!(
+                "invalid profile time: {}. profile time must be positive",
+                config.profile_time
+            );
+        }
+
+        if config.processes == 0 {
+            panic!("invalid number of processes: 0. at least one process is required");
+        }
+
+        if !total_time_string.is_empty() {
+            config.total_time = parse_duration(&total_time_string)
+                .filter(|&t| t > 0.0)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "invalid total time: {}. total time must be a positive number \
+                        followed by ms, s, m or h",
+                        total_time_string
+                    )
+                });
+        }
+
+        if !shard_string.is_empty() {
+            config.shard = Some(parse_shard(&shard_string).unwrap_or_else(|| {
+                panic!(
+                    "invalid shard: {}. shard must be K/N where 1 <= K <= N",
+                    shard_string
+                )
+            }));
+        }
+
+        if !perf_events_string.is_empty() {
+            config.perf_events = perf_events_string.split(',').map(|s| s.to_owned()).collect();
+            for name in config.perf_events.iter() {
+                if let Err(e) = PerfCounters::check_name(name) {
+                    exit_with_error(&e);
+                }
+            }
+        }
+
+        if !profiler_control_string.is_empty() {
+            config.profiler_control = Some(
+                profiler_control_string
+                    .parse()
+                    .unwrap_or_else(|e| panic!("{}. expected fifo:CTL[,ACK] or fd:CTL[,ACK]", e)),
+            );
+        }
+
+        if !rates_string.is_empty() {
+            config.rates = rates_string
+                .split(',')
+                .map(|s| s.parse().ok().filter(|&r: &f64| r > 0.0))
+                .collect::<Option<_>>()
+                .unwrap_or_else(|| {
+                    panic!(
+                        "invalid rate: {}. rates must be positive numbers of calls per second",
+                        rates_string
+                    )
+                });
+        }
+
+        if config.mode == BenchmarkMode::OpenLoop && config.rates.is_empty() {
+            panic!("open-loop mode requires the rates of calls given by --rate");
+        }
+
+        if config.mode != BenchmarkMode::OpenLoop && !config.rates.is_empty() {
+            panic!("--rate is only used by --mode open-loop");
+        }
+
+        if config.workers == 0 {
+            panic!("--workers must be at least 1");
+        }
+
+        if config.mode != BenchmarkMode::OpenLoop && config.workers != 1 {
+            panic!("--workers is only used by --mode open-loop");
+        }
+
+        if !merge_string.is_empty() {
+            config.merge = merge_string.split(',').map(|s| s.to_owned()).collect();
+        }
+
+        if !empty_loop_string.is_empty() {
+            config.empty_loop = Some(parse_pair(&empty_loop_string).unwrap_or_else(|| {
+                panic!("invalid empty loop: {}", empty_loop_string)
+            }));
+        }
+
+        if !timer_string.is_empty() {
+            config.timer = Some(
+                parse_pair(&timer_string)
+                    .unwrap_or_else(|| panic!("invalid timer: {}", timer_string)),
+            );
+        }
+
         config
     }
 
@@ -77,9 +518,118 @@ impl Default for BenchmarkConfig {
         BenchmarkConfig {
             filter: "".to_owned(),
             warmup_time: 2.0,
+            warmup_mode: WarmupMode::Fixed,
             measurement_time: 3.0,
             confidence_level: 0.95,
-            reporters_string: "console".to_owned()
+            processes: 1,
+            randomize_layout: false,
+            cold_start: 0,
+            isolate: false,
+            cache_cold: false,
+            measure_stack: false,
+            profile_time: 0.0,
+            seed: 0,
+            measure: MeasurementKind::WallTime,
+            mode: BenchmarkMode::Throughput,
+            rates: Vec::new(),
+            workers: 1,
+            perf_events: Vec::new(),
+            profiler_control: None,
+            subtract_overhead: false,
+            timeout: 0.0,
+            total_time: 0.0,
+            resume: false,
+            shard: None,
+            merge: Vec::new(),
+            reporters_string: "console".to_owned(),
+            child: false,
+            skip: 0,
+            empty_loop: None,
+            timer: None,
         }
     }
 }
+
+#[cfg(feature = "argparse")]
+fn parse_pair(s: &str) -> Option<(f64, f64)> {
+    let idx = s.find(',')?;
+    let mean = s[..idx].parse().ok()?;
+    let stderr = s[idx + 1..].parse().ok()?;
+    Some((mean, stderr))
+}
+
+#[cfg(feature = "argparse")]
+fn parse_shard(s: &str) -> Option<(usize, usize)> {
+    let idx = s.find('/')?;
+    let index: usize = s[..idx].trim().parse().ok()?;
+    let count: usize = s[idx + 1..].trim().parse().ok()?;
+
+    if 1 <= index && index <= count {
+        Some((index, count))
+    } else {
+        None
+    }
+}
+
+/// Parse a duration such as `90`, `90s`, `10m` or `1.5h` into seconds
+#[cfg(feature = "argparse")]
+fn parse_duration(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (value, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(idx) => (&s[..idx], &s[idx..]),
+        None => (s, "s"),
+    };
+
+    let scale = match unit {
+        "ms" => 1e-3,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+    };
+
+    let value: f64 = value.trim().parse().ok()?;
+    if value.is_finite() && value >= 0.0 {
+        Some(value * scale)
+    } else {
+        None
+    }
+}
+
+#[cfg(all(test, feature = "argparse"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration() {
+        assert_eq!(parse_duration("90"), Some(90.0));
+        assert_eq!(parse_duration("2.5s"), Some(2.5));
+        assert_eq!(parse_duration("10m"), Some(600.0));
+        assert_eq!(parse_duration("1h"), Some(3600.0));
+        assert_eq!(parse_duration("500ms"), Some(0.5));
+        assert_eq!(parse_duration("10d"), None);
+        assert_eq!(parse_duration("-1s"), None);
+        assert_eq!(parse_duration("m"), None);
+    }
+
+    #[test]
+    fn profiler_control() {
+        assert_eq!(
+            "fifo:ctl,ack".parse(),
+            Ok(ProfilerControl::Fifo {
+                control: PathBuf::from("ctl"),
+                ack: Some(PathBuf::from("ack")),
+            })
+        );
+        assert_eq!(
+            "fd:10".parse(),
+            Ok(ProfilerControl::Fd {
+                control: 10,
+                ack: None,
+            })
+        );
+        assert!("fd:ctl".parse::<ProfilerControl>().is_err());
+        assert!("fifo:".parse::<ProfilerControl>().is_err());
+        assert!("pipe:ctl".parse::<ProfilerControl>().is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+
+use crate::config::ProfilerControl;
+
+/// Connection to an external profiler which records only while benchmarks are
+/// measured, in the protocol of `perf record --control`: the commands `enable`
+/// and `disable` are written to the control file, and if an acknowledgement
+/// file is given, each command is acknowledged by `ack` on it.
+#[derive(Debug)]
+pub(crate) struct Controller {
+    control: File,
+    ack: Option<File>,
+}
+
+impl Controller {
+    /// Open the files of `target`. Opening a FIFO blocks until the profiler
+    /// opens its other end.
+    pub fn open(target: &ProfilerControl) -> io::Result<Self> {
+        match *target {
+            ProfilerControl::Fifo {
+                ref control,
+                ref ack,
+            } => Ok(Controller {
+                control: OpenOptions::new().write(true).open(control)?,
+                ack: match ack {
+                    Some(ack) => Some(File::open(ack)?),
+                    None => None,
+                },
+            }),
+            ProfilerControl::Fd { control, ack } => Ok(Controller {
+                control: imp::dup(control)?,
+                ack: match ack {
+                    Some(ack) => Some(imp::dup(ack)?),
+                    None => None,
+                },
+            }),
+        }
+    }
+
+    /// Tell the profiler to start recording
+    pub fn enable(&mut self) -> io::Result<()> {
+        self.send("enable")
+    }
+
+    /// Tell the profiler to start recording until the returned guard is
+    /// dropped, so that recording stops even if the benchmark panics
+    pub fn record(&mut self) -> io::Result<Recording> {
+        let controller = Controller {
+            control: self.control.try_clone()?,
+            ack: match self.ack {
+                Some(ref ack) => Some(ack.try_clone()?),
+                None => None,
+            },
+        };
+        self.enable()?;
+        Ok(Recording { controller })
+    }
+
+    /// Tell the profiler to stop recording
+    pub fn disable(&mut self) -> io::Result<()> {
+        self.send("disable")
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.control, "{}", command)?;
+        self.control.flush()?;
+
+        if let Some(ref mut ack) = self.ack {
+            // perf terminates the acknowledgement by a newline and a NUL byte,
+            // so read up to the newline and skip NUL bytes
+            let mut line = Vec::new();
+            let mut byte = [0u8];
+            loop {
+                if ack.read(&mut byte)? == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "profiler closed the acknowledgement file",
+                    ));
+                }
+                match byte[0] {
+                    b'\n' => break,
+                    0 => {}
+                    b => line.push(b),
+                }
+            }
+
+            if line != b"ack" {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "unexpected acknowledgement: {}",
+                        String::from_utf8_lossy(&line)
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Guard which tells the profiler to stop recording when dropped
+#[derive(Debug)]
+pub(crate) struct Recording {
+    controller: Controller,
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if let Err(e) = self.controller.disable() {
+            eprintln!("Warning: failed to control the profiler: {}", e);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::BorrowedFd;
+
+    /// Duplicate an inherited file descriptor, which is left open for the
+    /// profiler and other benchers
+    pub(super) fn dup(fd: i32) -> io::Result<File> {
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        Ok(File::from(fd.try_clone_to_owned()?))
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::fs::File;
+    use std::io;
+
+    pub(super) fn dup(_fd: i32) -> io::Result<File> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "file descriptors of profilers are only supported on Unix",
+        ))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::io::AsRawFd;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::process::Command;
+    use std::thread;
+
+    /// A thread reading commands in place of perf
+    #[test]
+    fn commands_are_acknowledged() {
+        let (control_reader, control_writer) = io::pipe().unwrap();
+        let (ack_reader, mut ack_writer) = io::pipe().unwrap();
+
+        let profiler = thread::spawn(move || {
+            let mut commands = Vec::new();
+            for line in BufReader::new(control_reader).lines() {
+                commands.push(line.unwrap());
+                ack_writer.write_all(b"ack\n\0").unwrap();
+            }
+            commands
+        });
+
+        let target = ProfilerControl::Fd {
+            control: control_writer.as_raw_fd(),
+            ack: Some(ack_reader.as_raw_fd()),
+        };
+        let mut controller = Controller::open(&target).unwrap();
+        controller.enable().unwrap();
+        controller.disable().unwrap();
+
+        // the profiler sees the end of the commands once all writers are closed
+        drop(controller);
+        drop(control_writer);
+        assert_eq!(profiler.join().unwrap(), vec!["enable", "disable"]);
+    }
+
+    /// FIFOs given by `fifo:CTL,ACK`, as created for `perf record --control`
+    #[test]
+    fn fifo_round_trip() {
+        let dir = env::temp_dir().join(format!("smbench-{}-fifo", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let control = dir.join("control");
+        let ack = dir.join("ack");
+        let status = Command::new("mkfifo")
+            .arg(&control)
+            .arg(&ack)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let (control_path, ack_path) = (control.clone(), ack.clone());
+        let profiler = thread::spawn(move || {
+            let control = File::open(control_path).unwrap();
+            let mut ack = OpenOptions::new().write(true).open(ack_path).unwrap();
+            let mut commands = Vec::new();
+            for line in BufReader::new(control).lines() {
+                commands.push(line.unwrap());
+                ack.write_all(b"ack\n\0").unwrap();
+            }
+            commands
+        });
+
+        let target: ProfilerControl = format!("fifo:{},{}", control.display(), ack.display())
+            .parse()
+            .unwrap();
+        let mut controller = Controller::open(&target).unwrap();
+
+        // recording stops when the benchmark panics
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _recording = controller.record().unwrap();
+            panic!("benchmark panicked");
+        }));
+        assert!(result.is_err());
+        drop(controller);
+
+        assert_eq!(profiler.join().unwrap(), vec!["enable", "disable"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
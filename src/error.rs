@@ -115,7 +115,11 @@ macro_rules! impl_error {
             fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
                 match self {
                     $(
-                        Self::$var $($args2)* => write!(f, $desc),
+                        Self::$var $($args2)* => {
+                            write!(f, $desc)?;
+                            $(write!(f, ": {}", $arg)?;)*
+                            Ok(())
+                        }
                     )*
                 }
             }
@@ -143,5 +147,21 @@ impl_error!(
             "invalid benchmark function",
             "{}",
         },
+        BenchmarkPanicked(t: String) {
+            "benchmark panicked",
+            "{}",
+        },
+        TimedOut(t: String) {
+            "benchmark timed out",
+            "{}",
+        },
+        Interrupted {
+            "benchmark interrupted",
+            "interrupted by Ctrl-C",
+        },
+        ProcessFailed(t: String) {
+            "child process failed",
+            "{}",
+        },
     }
 );
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::measurement::Unit;
+
 pub struct Time {
     second: f64,
     significants: u16,
@@ -37,6 +39,47 @@ impl fmt::Display for Time {
     }
 }
 
+pub struct Value {
+    value: f64,
+    unit: Unit,
+}
+
+/// Format `value` measured in `unit`
+#[inline]
+pub fn value(value: f64, unit: Unit) -> Value {
+    Value { value, unit }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let unit = match self.unit {
+            Unit::Seconds => return time(self.value).fmt(f),
+            Unit::Ratio => return fmt_float(f, self.value, 5, ""),
+            Unit::Cycles => "cycles",
+            Unit::Count => "",
+            Unit::Bytes => "B",
+        };
+
+        let mut value = self.value;
+        let abs_value = value.abs();
+        let prefix = if abs_value < 1e3 {
+            ""
+        } else if abs_value < 1e6 {
+            value /= 1e3;
+            "K"
+        } else if abs_value < 1e9 {
+            value /= 1e6;
+            "M"
+        } else {
+            value /= 1e9;
+            "G"
+        };
+
+        let suffix = format!(" {}{}", prefix, unit);
+        fmt_float(f, value, 5, suffix.trim_end())
+    }
+}
+
 pub struct Bytes {
     bytes: usize,
 }
@@ -77,13 +120,13 @@ fn fmt_float(f: &mut fmt::Formatter, value: f64, significants: u16, suffix: &str
     } else {
         let value_str = if value.is_nan() {
             "NaN"
-        } else if value == std::f64::INFINITY {
+        } else if value == f64::INFINITY {
             if f.sign_plus() {
                 "+inf"
             } else {
                 "inf"
             }
-        } else if value == std::f64::NEG_INFINITY {
+        } else if value == f64::NEG_INFINITY {
             "inf"
         } else {
             ""
@@ -97,7 +140,8 @@ fn fmt_float(f: &mut fmt::Formatter, value: f64, significants: u16, suffix: &str
 
 #[cfg(test)]
 mod tests {
-    use super::time;
+    use super::{time, value};
+    use crate::measurement::Unit;
 
     #[test]
     fn format_time() {
@@ -109,4 +153,14 @@ mod tests {
         assert_eq!(format!("{:>10}", time(1.23)), "  1.2300 s");
         assert_eq!(format!("{:>10}", time(-2.71828e-3)), "-2.7183 ms");
     }
+
+    #[test]
+    fn format_value() {
+        assert_eq!(format!("{}", value(1.5e-6, Unit::Seconds)), "1.5000 us");
+        assert_eq!(format!("{}", value(12.5, Unit::Cycles)), "12.500 cycles");
+        assert_eq!(format!("{}", value(2.5e6, Unit::Count)), "2.5000 M");
+        assert_eq!(format!("{}", value(123.0, Unit::Count)), "123.00");
+        assert_eq!(format!("{}", value(4096.0, Unit::Bytes)), "4.0960 KB");
+        assert_eq!(format!("{}", value(3.2, Unit::Ratio)), "3.2000");
+    }
 }
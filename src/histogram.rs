@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of bits of the mantissa which select the bucket within a power of
+/// two, which bounds the relative error of quantiles by `2^-SUB_BUCKET_BITS`
+const SUB_BUCKET_BITS: u32 = 7;
+const BUCKET_SHIFT: u32 = 52 - SUB_BUCKET_BITS;
+
+/// Histogram of non-negative values with logarithmically sized buckets, in the
+/// style of HdrHistogram.
+///
+/// Each power of two is divided into `2^7` linear buckets, so quantiles are
+/// accurate to less than 1% of the value regardless of its magnitude. Only
+/// non-empty buckets are stored, and histograms of the same quantity measured
+/// in different processes can be merged.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// count of values in each bucket, keyed by `bucket_index`
+    buckets: BTreeMap<u64, u64>,
+    count: u64,
+    /// largest recorded value, which is kept exactly
+    max: f64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a value. Negative values are recorded as zero.
+    #[inline]
+    pub fn record(&mut self, value: f64) {
+        let value = if value > 0.0 { value } else { 0.0 };
+        *self.buckets.entry(bucket_index(value)).or_insert(0) += 1;
+        self.count += 1;
+        self.max = f64::max(self.max, value);
+    }
+
+    /// Add the values recorded by `other`
+    pub fn merge(&mut self, other: &Histogram) {
+        for (&index, &count) in other.buckets.iter() {
+            *self.buckets.entry(index).or_insert(0) += count;
+        }
+        self.count += other.count;
+        self.max = f64::max(self.max, other.max);
+    }
+
+    /// Number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Largest recorded value
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    /// Value below which the fraction `q` of the recorded values fall, e.g.
+    /// the 99th percentile for `q = 0.99`. Returns 0 if the histogram is empty.
+    pub fn quantile(&self, q: f64) -> f64 {
+        let rank = (q * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (&index, &count) in self.buckets.iter() {
+            seen += count;
+            if seen >= rank {
+                // the midpoint of the bucket, which may not exceed the maximum
+                let mid = (bucket_start(index) + bucket_start(index + 1)) * 0.5;
+                return f64::min(mid, self.max);
+            }
+        }
+        self.max
+    }
+
+    /// Lower bound and count of each non-empty bucket in increasing order
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        self.buckets
+            .iter()
+            .map(|(&index, &count)| (bucket_start(index), count))
+    }
+}
+
+/// Encodes the histogram as the maximum followed by `index:count` of each
+/// non-empty bucket, separated by spaces
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:e}", self.max)?;
+        for (index, count) in self.buckets.iter() {
+            write!(f, " {}:{}", index, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Histogram {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid histogram: {}", s);
+
+        let mut fields = s.split_whitespace();
+        let max = fields
+            .next()
+            .and_then(|m| m.parse().ok())
+            .ok_or_else(invalid)?;
+
+        let mut histogram = Histogram {
+            buckets: BTreeMap::new(),
+            count: 0,
+            max,
+        };
+        for field in fields {
+            let idx = field.find(':').ok_or_else(invalid)?;
+            let index: u64 = field[..idx].parse().map_err(|_| invalid())?;
+            let count: u64 = field[idx + 1..].parse().map_err(|_| invalid())?;
+            *histogram.buckets.entry(index).or_insert(0) += count;
+            histogram.count += count;
+        }
+
+        Ok(histogram)
+    }
+}
+
+/// The exponent and the highest bits of the mantissa of a non-negative `f64`
+/// increase monotonically with the value
+#[inline]
+fn bucket_index(value: f64) -> u64 {
+    value.to_bits() >> BUCKET_SHIFT
+}
+
+fn bucket_start(index: u64) -> f64 {
+    f64::from_bits(index << BUCKET_SHIFT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_float_eq::*;
+
+    #[test]
+    fn quantiles() {
+        let mut histogram = Histogram::new();
+        for i in 1..=1000 {
+            histogram.record(i as f64 * 1e-6);
+        }
+        assert_eq!(histogram.count(), 1000);
+        assert_float_relative_eq!(histogram.quantile(0.5), 500e-6, 0.01);
+        assert_float_relative_eq!(histogram.quantile(0.99), 990e-6, 0.01);
+        assert_float_relative_eq!(histogram.quantile(1.0), 1000e-6, 0.01);
+        assert_eq!(histogram.max(), 1000e-6);
+        assert_eq!(Histogram::new().quantile(0.5), 0.0);
+
+        // merging and encoding keep all values
+        let mut merged = histogram.clone();
+        merged.merge(&histogram);
+        assert_eq!(merged.count(), 2000);
+        assert_float_relative_eq!(merged.quantile(0.5), 500e-6, 0.01);
+
+        let decoded: Histogram = merged.to_string().parse().unwrap();
+        assert_eq!(decoded, merged);
+    }
+}
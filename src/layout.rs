@@ -0,0 +1,93 @@
+use std::mem::MaybeUninit;
+
+use crate::common::black_box;
+use crate::memory::set_heap_padding;
+use crate::rng::Rng;
+
+/// Number of samples measured with each memory layout
+pub(crate) const SAMPLES_PER_LAYOUT: usize = 4;
+
+/// Maximum shift of the stack pointer in bytes
+const MAX_STACK_OFFSET: usize = 4096;
+
+/// Maximum padding of heap allocations in bytes
+const MAX_HEAP_PADDING: usize = 256;
+
+/// Randomly perturbed memory layout used to measure a sample
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MemoryLayout {
+    stack_offset: usize,
+    heap_seed: u64,
+}
+
+impl MemoryLayout {
+    pub fn random(rng: &mut Rng) -> Self {
+        let value = rng.next_u64();
+        Self {
+            stack_offset: value as usize % MAX_STACK_OFFSET,
+            heap_seed: rng.next_u64(),
+        }
+    }
+
+    /// Call `f` with the stack shifted and heap allocations padded
+    pub fn run(&self, f: &mut dyn FnMut()) {
+        // disable padding even if `f` panics
+        struct Guard;
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                set_heap_padding(0, 0);
+            }
+        }
+
+        let _guard = Guard;
+        set_heap_padding(MAX_HEAP_PADDING, self.heap_seed);
+        with_stack_offset(self.stack_offset, f);
+    }
+}
+
+#[inline(never)]
+fn pad<const N: usize>(f: &mut dyn FnMut()) {
+    let padding = MaybeUninit::<[u8; N]>::uninit();
+    black_box(&padding);
+    f();
+}
+
+macro_rules! pad_table {
+    ($step:expr; $($i:expr),*) => {
+        [$(pad::<{ $i * $step }>),*]
+    };
+}
+
+/// Call `f` with the stack pointer shifted by about `offset` bytes (in steps
+/// of 16 bytes, up to 4096 bytes)
+fn with_stack_offset(offset: usize, f: &mut dyn FnMut()) {
+    const FINE: [fn(&mut dyn FnMut()); 16] =
+        pad_table!(16; 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+    const COARSE: [fn(&mut dyn FnMut()); 16] =
+        pad_table!(256; 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+
+    let fine = FINE[(offset >> 4) & 15];
+    COARSE[(offset >> 8) & 15](&mut || fine(f));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::with_stack_offset;
+
+    fn stack_address(offset: usize) -> usize {
+        let mut address = 0;
+        with_stack_offset(offset, &mut || {
+            let local = 0u8;
+            address = crate::common::black_box(&local) as *const u8 as usize;
+        });
+        address
+    }
+
+    #[test]
+    fn stack_offset() {
+        // frame sizes depend on the compiler, so only check that the stack
+        // moved by most of the offset
+        let shift = stack_address(0) - stack_address(4000);
+        assert!(shift >= 3000, "shift = {}", shift);
+    }
+}
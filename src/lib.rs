@@ -5,13 +5,28 @@ mod macros;
 
 mod app;
 mod bench;
+mod budget;
+mod cache;
+mod calibration;
+mod checkpoint;
 mod common;
 mod config;
+mod control;
 mod error;
 mod fmt;
+mod histogram;
+mod layout;
+mod measurement;
+mod memory;
+mod process;
+mod profile;
 mod reporter;
+mod rng;
+mod signal;
+mod stack;
 mod stats;
 mod summary;
+mod watchdog;
 
 #[cfg(feature = "sysinfo")]
 pub mod sys;
@@ -20,5 +35,10 @@ pub use app::*;
 pub use bench::*;
 pub use common::*;
 pub use config::*;
+pub use error::Error;
+pub use histogram::Histogram;
+pub use measurement::{Measurement, Unit, WallTime};
+pub use memory::TracingAllocator;
+pub use rng::{RandomInt, Rng};
 #[doc(hidden)]
 pub use reporter::*;
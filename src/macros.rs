@@ -26,6 +26,14 @@ macro_rules! smbench_group {
     }
 }
 
+#[macro_export]
+macro_rules! smbench_trace_memory {
+    () => {
+        #[global_allocator]
+        static SMBENCH_ALLOCATOR: $crate::TracingAllocator = $crate::TracingAllocator::new();
+    };
+}
+
 #[macro_export]
 macro_rules! smbench_main {
     ($($group:path),*) => {
@@ -38,9 +46,11 @@ macro_rules! smbench_main {
             let config = Arc::new(BenchmarkConfig::from_args());
             let mut app = App::from_config(config);
 
-            $(
-                app.bench_group(&$group());
-            )*
+            let groups = [$($group()),*];
+            app.plan(&groups);
+            for group in groups.iter() {
+                app.bench_group(group);
+            }
 
             app.finish();
         }
@@ -0,0 +1,88 @@
+use std::io;
+use std::os::raw::c_int;
+
+use super::{Measurement, Unit};
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const CLOCK_PROCESS_CPUTIME_ID: c_int = 12;
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+const CLOCK_THREAD_CPUTIME_ID: c_int = 16;
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const CLOCK_PROCESS_CPUTIME_ID: c_int = 2;
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+const CLOCK_THREAD_CPUTIME_ID: c_int = 3;
+
+/// CPU time consumed by the benchmark thread or by the whole process.
+///
+/// Unlike wall-clock time, CPU time does not include time spent waiting for
+/// I/O, locks or the scheduler.
+#[derive(Clone, Debug)]
+pub struct CpuTime {
+    clock: c_int,
+}
+
+impl CpuTime {
+    /// CPU time of the thread running the benchmark (`CLOCK_THREAD_CPUTIME_ID`)
+    pub fn thread() -> Self {
+        CpuTime {
+            clock: CLOCK_THREAD_CPUTIME_ID,
+        }
+    }
+
+    /// CPU time of all threads of the process (`CLOCK_PROCESS_CPUTIME_ID`)
+    pub fn process() -> Self {
+        CpuTime {
+            clock: CLOCK_PROCESS_CPUTIME_ID,
+        }
+    }
+
+    #[inline]
+    fn now(&self) -> f64 {
+        let mut ts = ffi::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        if unsafe { ffi::clock_gettime(self.clock, &mut ts) } != 0 {
+            panic!("clock_gettime failed: {}", io::Error::last_os_error());
+        }
+        ts.tv_sec as f64 + ts.tv_nsec as f64 * 1e-9
+    }
+}
+
+impl Measurement for CpuTime {
+    fn name(&self) -> &str {
+        if self.clock == CLOCK_THREAD_CPUTIME_ID {
+            "cpu-time"
+        } else {
+            "process-cpu-time"
+        }
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Seconds
+    }
+
+    #[inline]
+    fn start(&mut self) -> f64 {
+        self.now()
+    }
+
+    #[inline]
+    fn end(&mut self, intermediate: f64) -> f64 {
+        self.now() - intermediate
+    }
+}
+
+mod ffi {
+    use std::os::raw::{c_int, c_long};
+
+    #[repr(C)]
+    pub struct timespec {
+        pub tv_sec: c_long,
+        pub tv_nsec: c_long,
+    }
+
+    extern "C" {
+        pub fn clock_gettime(clock_id: c_int, tp: *mut timespec) -> c_int;
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+use crate::config::MeasurementKind;
+
+#[cfg(unix)]
+mod cpu_time;
+#[cfg(unix)]
+pub use cpu_time::CpuTime;
+
+#[cfg(target_os = "linux")]
+mod perf;
+#[cfg(target_os = "linux")]
+pub(crate) use perf::PerfCounters;
+
+#[cfg(target_arch = "x86_64")]
+pub(crate) mod tsc;
+#[cfg(target_arch = "x86_64")]
+pub use tsc::Cycles;
+
+/// What a benchmark measures, e.g. wall-clock time.
+///
+/// `Bencher` calls `start` right before the timed loop and `end` right after
+/// it, and fits the values against the number of iterations in the same way as
+/// wall-clock time. Values are `f64` in the unit returned by `unit`.
+pub trait Measurement: Send {
+    /// Name of the measured quantity, e.g. `time`
+    fn name(&self) -> &str;
+
+    /// Unit of the values, which is written to reports and decides how
+    /// `format` formats values by default
+    fn unit(&self) -> Unit;
+
+    /// Format a value (or a statistic of values) for reporters
+    fn format(&self, value: f64, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&crate::fmt::value(value, self.unit()), f)
+    }
+
+    /// Start measuring and return an intermediate value passed to `end`
+    fn start(&mut self) -> f64;
+
+    /// Finish measuring and return the value measured since `start`
+    fn end(&mut self, intermediate: f64) -> f64;
+
+    /// Combine values of separately measured parts of a sample
+    fn add(&self, v1: f64, v2: f64) -> f64 {
+        v1 + v2
+    }
+
+    /// Value of an empty measurement
+    fn zero(&self) -> f64 {
+        0.0
+    }
+}
+
+impl fmt::Debug for dyn Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Measurement").field(&self.name()).finish()
+    }
+}
+
+/// Check that the measurement selected by `--measure` is supported on this
+/// platform
+pub(crate) fn check_supported(kind: MeasurementKind) -> Result<(), String> {
+    match kind {
+        MeasurementKind::WallTime => Ok(()),
+        #[cfg(unix)]
+        MeasurementKind::ThreadCpuTime | MeasurementKind::ProcessCpuTime => Ok(()),
+        #[cfg(target_arch = "x86_64")]
+        MeasurementKind::Cycles => match Cycles::new() {
+            Some(_) => Ok(()),
+            None => Err("measuring cycles requires a CPU with invariant TSC".to_owned()),
+        },
+        #[allow(unreachable_patterns)]
+        _ => Err(format!("{:?} is not supported on this platform", kind)),
+    }
+}
+
+/// Create the measurement selected by `--measure`
+///
+/// # Panics
+///
+/// Panics if the measurement is not supported on this platform, which
+/// `BenchmarkConfig::from_args` rejects beforehand.
+pub(crate) fn from_kind(kind: MeasurementKind) -> Box<dyn Measurement> {
+    if let Err(e) = check_supported(kind) {
+        panic!("{}", e);
+    }
+
+    match kind {
+        #[cfg(unix)]
+        MeasurementKind::ThreadCpuTime => Box::new(CpuTime::thread()),
+        #[cfg(unix)]
+        MeasurementKind::ProcessCpuTime => Box::new(CpuTime::process()),
+        #[cfg(target_arch = "x86_64")]
+        MeasurementKind::Cycles => Box::new(Cycles::new().unwrap()),
+        _ => Box::new(WallTime::new()),
+    }
+}
+
+/// Performance counters are only supported on Linux
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub(crate) struct PerfCounters;
+
+#[cfg(not(target_os = "linux"))]
+impl PerfCounters {
+    pub fn check_name(_name: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn open(_names: &[String], warn: bool) -> Self {
+        if warn {
+            eprintln!("Warning: perf events are only supported on Linux");
+        }
+        PerfCounters
+    }
+
+    pub fn names(&self) -> &[String] {
+        &[]
+    }
+
+    pub fn reset(&mut self) {}
+
+    pub fn enable(&mut self) {}
+
+    pub fn disable(&mut self) {}
+
+    pub fn read(&mut self) -> &[u64] {
+        &[]
+    }
+}
+
+/// Unit of measured values
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    Seconds,
+    Cycles,
+    Count,
+    Bytes,
+    Ratio,
+}
+
+impl Unit {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Unit::Seconds => "seconds",
+            Unit::Cycles => "cycles",
+            Unit::Count => "count",
+            Unit::Bytes => "bytes",
+            Unit::Ratio => "ratio",
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "seconds" => Ok(Unit::Seconds),
+            "cycles" => Ok(Unit::Cycles),
+            "count" => Ok(Unit::Count),
+            "bytes" => Ok(Unit::Bytes),
+            "ratio" => Ok(Unit::Ratio),
+            _ => Err(format!("invalid unit: {}", s)),
+        }
+    }
+}
+
+/// Wall-clock time measured by `Instant` (the default measurement)
+#[derive(Clone, Debug)]
+pub struct WallTime {
+    base: Instant,
+}
+
+impl WallTime {
+    pub fn new() -> Self {
+        WallTime {
+            base: Instant::now(),
+        }
+    }
+}
+
+impl Default for WallTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Measurement for WallTime {
+    fn name(&self) -> &str {
+        "time"
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Seconds
+    }
+
+    #[inline]
+    fn start(&mut self) -> f64 {
+        self.base.elapsed().as_secs_f64()
+    }
+
+    #[inline]
+    fn end(&mut self, intermediate: f64) -> f64 {
+        self.base.elapsed().as_secs_f64() - intermediate
+    }
+}
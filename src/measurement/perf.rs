@@ -0,0 +1,310 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::io::AsRawFd;
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+
+const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const PERF_FORMAT_GROUP: u64 = 1 << 3;
+
+const FLAG_DISABLED: u64 = 1 << 0;
+const FLAG_EXCLUDE_KERNEL: u64 = 1 << 5;
+const FLAG_EXCLUDE_HV: u64 = 1 << 6;
+
+const PERF_EVENT_IOC_ENABLE: c_ulong = 0x2400;
+const PERF_EVENT_IOC_DISABLE: c_ulong = 0x2401;
+const PERF_EVENT_IOC_RESET: c_ulong = 0x2403;
+const PERF_IOC_FLAG_GROUP: c_ulong = 1;
+
+#[cfg(target_arch = "x86_64")]
+const SYS_PERF_EVENT_OPEN: std::os::raw::c_long = 298;
+#[cfg(target_arch = "x86")]
+const SYS_PERF_EVENT_OPEN: std::os::raw::c_long = 336;
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+const SYS_PERF_EVENT_OPEN: std::os::raw::c_long = 241;
+#[cfg(target_arch = "arm")]
+const SYS_PERF_EVENT_OPEN: std::os::raw::c_long = 364;
+
+/// Events which can be given to `--perf-events`
+const EVENTS: &[(&str, u32, u64)] = &[
+    ("instructions", PERF_TYPE_HARDWARE, 1),
+    ("cycles", PERF_TYPE_HARDWARE, 0),
+    ("branch-misses", PERF_TYPE_HARDWARE, 5),
+    ("cache-misses", PERF_TYPE_HARDWARE, 3),
+    ("task-clock", PERF_TYPE_SOFTWARE, 1),
+    ("page-faults", PERF_TYPE_SOFTWARE, 2),
+    ("context-switches", PERF_TYPE_SOFTWARE, 3),
+];
+
+/// Names of all supported events
+fn event_names() -> impl Iterator<Item = &'static str> {
+    EVENTS.iter().map(|e| e.0)
+}
+
+/// Hardware and software performance counters of the current thread, opened
+/// with `perf_event_open` and counted as a group around each sample.
+#[derive(Debug)]
+pub(crate) struct PerfCounters {
+    /// names of the opened events, in order of the values read from `leader`
+    names: Vec<String>,
+    leader: Option<File>,
+    /// other members of the group, which must be kept open
+    members: Vec<File>,
+    values: Vec<u64>,
+    /// time the group was enabled and running in nanoseconds at the last reset
+    times: (u64, u64),
+    /// whether to warn if counts are scaled, which is done once
+    warn: bool,
+}
+
+impl PerfCounters {
+    /// Check that `name` is one of the supported events
+    pub fn check_name(name: &str) -> Result<(), String> {
+        if event_names().any(|e| e == name) {
+            return Ok(());
+        }
+        Err(format!(
+            "unknown perf event: {}. supported events are {}",
+            name,
+            event_names().collect::<Vec<_>>().join(", ")
+        ))
+    }
+
+    /// Open the events named in `names` for the calling thread.
+    ///
+    /// Events which are not allowed (e.g. hardware counters in a virtual
+    /// machine or with a restrictive `perf_event_paranoid`) are skipped, with a
+    /// warning if `warn` is set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a name is not one of the supported events, which
+    /// `BenchmarkConfig::from_args` rejects beforehand.
+    pub fn open(names: &[String], warn: bool) -> Self {
+        let mut counters = PerfCounters {
+            names: Vec::new(),
+            leader: None,
+            members: Vec::new(),
+            values: Vec::new(),
+            times: (0, 0),
+            warn,
+        };
+
+        let mut skipped = Vec::new();
+        for name in names {
+            if let Err(e) = Self::check_name(name) {
+                panic!("{}", e);
+            }
+            let &(_, type_, config) = EVENTS.iter().find(|e| e.0 == name).unwrap();
+
+            let group_fd = counters.leader.as_ref().map_or(-1, |f| f.as_raw_fd());
+            match open_event(type_, config, group_fd) {
+                Ok(file) => {
+                    if counters.leader.is_none() {
+                        counters.leader = Some(file);
+                    } else {
+                        counters.members.push(file);
+                    }
+                    counters.names.push(name.clone());
+                }
+                Err(e) => skipped.push(format!("{} ({})", name, e)),
+            }
+        }
+
+        if warn && !skipped.is_empty() {
+            eprintln!(
+                "Warning: skipping perf events which are not available: {}",
+                skipped.join(", ")
+            );
+        }
+
+        counters.values = vec![0; counters.names.len()];
+        counters
+    }
+
+    /// Names of the opened events
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Reset all counters to zero
+    #[inline]
+    pub fn reset(&mut self) {
+        self.ioctl(PERF_EVENT_IOC_RESET);
+
+        // resetting does not reset the times of the group
+        let mut buf = [0u64; EVENTS.len() + 3];
+        if let Some(times) = self.read_group(&mut buf) {
+            self.times = times;
+        }
+    }
+
+    #[inline]
+    pub fn enable(&mut self) {
+        self.ioctl(PERF_EVENT_IOC_ENABLE);
+    }
+
+    #[inline]
+    pub fn disable(&mut self) {
+        self.ioctl(PERF_EVENT_IOC_DISABLE);
+    }
+
+    /// Read the counters and return their values since the last reset.
+    ///
+    /// If the group shared the counters of the CPU with other events
+    /// (multiplexing), the values are scaled up by the fraction of time the
+    /// group was counting.
+    pub fn read(&mut self) -> &[u64] {
+        let mut buf = [0u64; EVENTS.len() + 3];
+        let (enabled, running) = match self.read_group(&mut buf) {
+            Some(times) => times,
+            None => return &self.values,
+        };
+
+        let enabled = enabled.saturating_sub(self.times.0);
+        let running = running.saturating_sub(self.times.1);
+        let scale = if running > 0 && running < enabled {
+            if self.warn {
+                eprintln!(
+                    "Warning: perf events were multiplexed with other events. \
+                    counts are scaled by the time they were counted"
+                );
+                self.warn = false;
+            }
+            enabled as f64 / running as f64
+        } else {
+            1.0
+        };
+
+        for (value, &raw) in self.values.iter_mut().zip(buf[3..].iter()) {
+            *value = (raw as f64 * scale).round() as u64;
+        }
+        &self.values
+    }
+
+    /// Read the values of the group into `buf` and return the times the group
+    /// was enabled and running
+    fn read_group(&mut self, buf: &mut [u64; EVENTS.len() + 3]) -> Option<(u64, u64)> {
+        let leader = self.leader.as_mut()?;
+
+        // the number of events, the times and the values of the events
+        let mut bytes = [0u8; 8 * (EVENTS.len() + 3)];
+        let bytes = &mut bytes[..8 * (self.values.len() + 3)];
+        leader.read_exact(bytes).ok()?;
+        for (word, chunk) in buf.iter_mut().zip(bytes.chunks(8)) {
+            let mut b = [0; 8];
+            b.copy_from_slice(chunk);
+            *word = u64::from_ne_bytes(b);
+        }
+        Some((buf[1], buf[2]))
+    }
+
+    #[inline]
+    fn ioctl(&self, request: c_ulong) {
+        if let Some(ref leader) = self.leader {
+            unsafe {
+                ffi::ioctl(leader.as_raw_fd(), request, PERF_IOC_FLAG_GROUP);
+            }
+        }
+    }
+}
+
+fn open_event(type_: u32, config: u64, group_fd: c_int) -> io::Result<File> {
+    let mut attr = ffi::perf_event_attr {
+        type_,
+        size: std::mem::size_of::<ffi::perf_event_attr>() as u32,
+        config,
+        sample_period: 0,
+        sample_type: 0,
+        read_format: PERF_FORMAT_GROUP
+            | PERF_FORMAT_TOTAL_TIME_ENABLED
+            | PERF_FORMAT_TOTAL_TIME_RUNNING,
+        flags: FLAG_DISABLED,
+        wakeup_events: 0,
+        bp_type: 0,
+        config1: 0,
+    };
+
+    // only the leader starts disabled, members follow the leader
+    if group_fd != -1 {
+        attr.flags = 0;
+    }
+
+    // counting the kernel is often not allowed, so retry for user space only
+    let mut fd = perf_event_open(&attr, group_fd);
+    if fd.is_err() {
+        attr.flags |= FLAG_EXCLUDE_KERNEL | FLAG_EXCLUDE_HV;
+        fd = perf_event_open(&attr, group_fd);
+    }
+    fd
+}
+
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "arm"
+))]
+fn perf_event_open(attr: &ffi::perf_event_attr, group_fd: c_int) -> io::Result<File> {
+    use std::os::unix::io::FromRawFd;
+
+    // measure the calling thread on any CPU
+    let fd = unsafe {
+        ffi::syscall(
+            SYS_PERF_EVENT_OPEN,
+            attr as *const ffi::perf_event_attr,
+            0 as c_int,
+            -1 as c_int,
+            group_fd,
+            0 as c_ulong,
+        )
+    };
+
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { File::from_raw_fd(fd as c_int) })
+    }
+}
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "arm"
+)))]
+fn perf_event_open(_attr: &ffi::perf_event_attr, _group_fd: c_int) -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "perf_event_open is not supported on this architecture",
+    ))
+}
+
+mod ffi {
+    use std::os::raw::{c_int, c_long, c_ulong};
+
+    /// First version of `struct perf_event_attr` (`PERF_ATTR_SIZE_VER0`)
+    #[repr(C)]
+    pub struct perf_event_attr {
+        pub type_: u32,
+        pub size: u32,
+        pub config: u64,
+        pub sample_period: u64,
+        pub sample_type: u64,
+        pub read_format: u64,
+        pub flags: u64,
+        pub wakeup_events: u32,
+        pub bp_type: u32,
+        pub config1: u64,
+    }
+
+    extern "C" {
+        pub fn syscall(number: c_long, ...) -> c_long;
+        pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+}
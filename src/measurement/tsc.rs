@@ -0,0 +1,98 @@
+use std::arch::x86_64::{__cpuid, __rdtscp, _mm_lfence, _rdtsc};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use super::{Measurement, Unit};
+
+/// Time spent calibrating the TSC frequency against `Instant`
+const CALIBRATION_TIME: Duration = Duration::from_millis(50);
+
+/// Reference cycles counted by the time stamp counter (TSC).
+///
+/// The TSC ticks at a constant rate on CPUs with invariant TSC, regardless of
+/// frequency scaling, and is read with fences so that the timed instructions
+/// do not move across the reads.
+#[derive(Clone, Debug)]
+pub struct Cycles {
+    /// counter value when the measurement was created, subtracted from every
+    /// reading so that readings fit in the mantissa of `f64`
+    base: u64,
+    rdtscp: bool,
+}
+
+impl Cycles {
+    /// Returns `None` if the CPU does not have an invariant TSC
+    pub fn new() -> Option<Self> {
+        if !has_invariant_tsc() {
+            return None;
+        }
+
+        Some(Cycles {
+            base: unsafe { _rdtsc() },
+            rdtscp: has_rdtscp(),
+        })
+    }
+}
+
+impl Measurement for Cycles {
+    fn name(&self) -> &str {
+        "cycles"
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Cycles
+    }
+
+    #[inline]
+    fn start(&mut self) -> f64 {
+        let tsc = unsafe {
+            _mm_lfence();
+            let tsc = _rdtsc();
+            _mm_lfence();
+            tsc
+        };
+        tsc.wrapping_sub(self.base) as f64
+    }
+
+    #[inline]
+    fn end(&mut self, intermediate: f64) -> f64 {
+        let tsc = unsafe {
+            let tsc = if self.rdtscp {
+                let mut aux = 0;
+                __rdtscp(&mut aux)
+            } else {
+                _mm_lfence();
+                _rdtsc()
+            };
+            _mm_lfence();
+            tsc
+        };
+        tsc.wrapping_sub(self.base) as f64 - intermediate
+    }
+}
+
+/// Frequency of the TSC in Hz, calibrated against `Instant` on the first call
+pub(crate) fn frequency() -> f64 {
+    static FREQUENCY: OnceLock<f64> = OnceLock::new();
+    *FREQUENCY.get_or_init(|| {
+        let start = Instant::now();
+        let start_tsc = unsafe { _rdtsc() };
+        while start.elapsed() < CALIBRATION_TIME {}
+        let end_tsc = unsafe { _rdtsc() };
+        let elapsed = start.elapsed();
+
+        end_tsc.wrapping_sub(start_tsc) as f64 / elapsed.as_secs_f64()
+    })
+}
+
+fn has_invariant_tsc() -> bool {
+    // CPUID.80000007H:EDX[8]
+    let max_extended_leaf = __cpuid(0x8000_0000).eax;
+    max_extended_leaf >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+fn has_rdtscp() -> bool {
+    // CPUID.80000001H:EDX[27]
+    let max_extended_leaf = __cpuid(0x8000_0000).eax;
+    max_extended_leaf >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 27) != 0
+}
@@ -0,0 +1,211 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ptr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::rng::{mix, GOLDEN_GAMMA};
+
+/// Maximum amount of random padding in bytes, which must be a power of two (0
+/// means padding is disabled)
+static MAX_PADDING: AtomicUsize = AtomicUsize::new(0);
+static PADDING_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Number and total size of the allocations made so far
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Alignment of unpadded allocations. Padded allocations are placed
+/// `HEADER_SIZE` bytes off this alignment, so that `dealloc` can tell them
+/// apart by the address alone.
+const TAG_ALIGN: usize = 16;
+
+/// Size of the header before padded allocations, which holds the offset of
+/// the allocation from the start of the underlying block
+const HEADER_SIZE: usize = 8;
+
+/// Global allocator installed by `smbench_trace_memory!()` macro, which
+/// counts heap allocations and their bytes, reported per iteration as the
+/// `allocations` and `allocated_bytes` counters. It also pads heap allocations
+/// by random amounts while the memory layout is randomized (see
+/// `--randomize-layout`).
+///
+/// Allocations with an alignment of at most 8 bytes are padded. While padding
+/// is disabled, allocations are passed to the system allocator without a
+/// header.
+pub struct TracingAllocator;
+
+impl TracingAllocator {
+    #[inline]
+    pub const fn new() -> Self {
+        TracingAllocator
+    }
+}
+
+impl Default for TracingAllocator {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[inline]
+fn trace(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+/// Number and total size of the allocations made so far, or `None` if
+/// `TracingAllocator` is not the global allocator
+pub(crate) fn allocations() -> Option<(u64, u64)> {
+    // the harness allocates before running benchmarks
+    let count = ALLOCATIONS.load(Ordering::Relaxed);
+    if count == 0 {
+        return None;
+    }
+    Some((count, ALLOCATED_BYTES.load(Ordering::Relaxed)))
+}
+
+#[inline]
+fn padding(max: usize) -> usize {
+    // data race between threads only affects the quality of randomness
+    let state = PADDING_STATE
+        .load(Ordering::Relaxed)
+        .wrapping_add(GOLDEN_GAMMA);
+    PADDING_STATE.store(state, Ordering::Relaxed);
+    mix(state) as usize & (max - 1)
+}
+
+/// Layout of an unpadded allocation, which is aligned to at least
+/// `TAG_ALIGN` (this is what `malloc` returns anyway on 64-bit platforms)
+#[inline]
+fn plain_layout(layout: Layout) -> Layout {
+    if layout.align() < TAG_ALIGN {
+        unsafe { Layout::from_size_align_unchecked(layout.size().max(TAG_ALIGN), TAG_ALIGN) }
+    } else {
+        layout
+    }
+}
+
+#[inline]
+fn is_padded(ptr: *mut u8, layout: Layout) -> bool {
+    layout.align() < TAG_ALIGN && ptr as usize & (TAG_ALIGN - 1) != 0
+}
+
+/// Layout of the underlying block of a padded allocation whose pointer is
+/// `offset` bytes after the start of the block
+#[inline]
+fn padded_layout(layout: Layout, offset: usize) -> Option<Layout> {
+    let size = layout.size().checked_add(offset)?;
+    Layout::from_size_align(size, TAG_ALIGN).ok()
+}
+
+unsafe impl GlobalAlloc for TracingAllocator {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        trace(layout.size());
+        let max = MAX_PADDING.load(Ordering::Relaxed);
+        if max == 0 || layout.align() > HEADER_SIZE {
+            return System.alloc(plain_layout(layout));
+        }
+
+        // `offset` is `HEADER_SIZE` modulo `TAG_ALIGN`
+        let offset = HEADER_SIZE + (padding(max) & !(TAG_ALIGN - 1));
+        let inner = match padded_layout(layout, offset) {
+            Some(inner) => inner,
+            None => return ptr::null_mut(),
+        };
+
+        let base = System.alloc(inner);
+        if base.is_null() {
+            return base;
+        }
+
+        let ptr = base.add(offset);
+        (ptr.sub(HEADER_SIZE) as *mut usize).write(offset);
+        ptr
+    }
+
+    #[inline]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if MAX_PADDING.load(Ordering::Relaxed) == 0 {
+            trace(layout.size());
+            return System.alloc_zeroed(plain_layout(layout));
+        }
+
+        let ptr = self.alloc(layout);
+        if !ptr.is_null() {
+            ptr::write_bytes(ptr, 0, layout.size());
+        }
+        ptr
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !is_padded(ptr, layout) {
+            return System.dealloc(ptr, plain_layout(layout));
+        }
+
+        let offset = (ptr.sub(HEADER_SIZE) as *const usize).read();
+        let inner = Layout::from_size_align_unchecked(layout.size() + offset, TAG_ALIGN);
+        System.dealloc(ptr.sub(offset), inner);
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if !is_padded(ptr, layout) && MAX_PADDING.load(Ordering::Relaxed) == 0 {
+            let new_size = if layout.align() < TAG_ALIGN {
+                new_size.max(TAG_ALIGN)
+            } else {
+                new_size
+            };
+            trace(new_size);
+            return System.realloc(ptr, plain_layout(layout), new_size);
+        }
+
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+/// Pad subsequent heap allocations by random amounts less than `max` bytes.
+/// The sequence of paddings is determined by `seed`. Padding is disabled if
+/// `max` is 0.
+pub(crate) fn set_heap_padding(max: usize, seed: u64) {
+    debug_assert!(max == 0 || max.is_power_of_two());
+    PADDING_STATE.store(seed, Ordering::Relaxed);
+    MAX_PADDING.store(max, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_and_plain_allocations() {
+        let allocator = TracingAllocator::new();
+        let layout = Layout::from_size_align(24, 8).unwrap();
+
+        unsafe {
+            let plain = allocator.alloc(layout);
+            assert!(!is_padded(plain, layout));
+
+            set_heap_padding(256, 1);
+            let padded: Vec<_> = (0..16).map(|_| allocator.alloc(layout)).collect();
+            set_heap_padding(0, 0);
+
+            // blocks allocated in either mode are freed in the other one
+            for &ptr in padded.iter() {
+                assert!(is_padded(ptr, layout));
+                assert_eq!(ptr as usize % layout.align(), 0);
+                ptr.write_bytes(0xff, layout.size());
+                allocator.dealloc(ptr, layout);
+            }
+            let plain = allocator.realloc(plain, layout, 4096);
+            allocator.dealloc(plain, Layout::from_size_align(4096, 8).unwrap());
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::bench::BenchmarkResult;
+use crate::calibration::{self, Timer};
+use crate::config::BenchmarkConfig;
+use crate::error::Error;
+use crate::reporter::{parse_raw_line, RawEvent, PROTOCOL_VERSION};
+use crate::signal;
+use crate::stats::Distribution;
+
+/// Results of the benchmark suite measured in fresh child processes
+pub(crate) struct ProcessResults {
+    processes: usize,
+    results: HashMap<(String, String), Vec<Result<BenchmarkResult, Error>>>,
+}
+
+impl ProcessResults {
+    /// Run the whole benchmark suite in `processes` child processes, one after
+    /// another, and collect their results.
+    ///
+    /// If a child process is aborted (e.g. by timeout or crash), a new child
+    /// process is spawned to measure the rest of the suite. With `--isolate`,
+    /// a new child process is spawned for every benchmark. No more child
+    /// processes are spawned if one writes a different `PROTOCOL_VERSION`.
+    ///
+    /// The empty loop is measured once here and passed to the child
+    /// processes, as is the `timer` calibrated by this process. The time budget given by `--total-time`, less the time of
+    /// that measurement, is divided equally among the processes.
+    pub fn collect(config: &BenchmarkConfig, timer: Timer) -> Self {
+        let processes = config.processes;
+        let mut results: HashMap<_, Vec<_>> = HashMap::new();
+
+        let start = Instant::now();
+        let empty_loop_args = match calibration::empty_loop(config) {
+            Some(e) => {
+                let value = format!("{},{}", e.mean(), e.variance().sqrt());
+                vec!["--smbench-empty-loop".to_owned(), value]
+            }
+            None => Vec::new(),
+        };
+        let timer_value = format!("{},{}", timer.resolution, timer.overhead);
+        let total_time = f64::max(config.total_time - start.elapsed().as_secs_f64(), 0.0);
+
+        'processes: for i in 1..=processes {
+            eprintln!("Running benchmarks in process {}/{}", i, processes);
+            let deadline =
+                Instant::now() + Duration::from_secs_f64(total_time / processes as f64);
+
+            // number of benchmarks started in this process
+            let mut started = 0;
+
+            loop {
+                let mut child = Command::new(env::current_exe().unwrap())
+                    .args(env::args_os().skip(1))
+                    .args(["--processes", "1", "--reporters", "raw", "--smbench-child"])
+                    .args(["--smbench-skip", &started.to_string()])
+                    .args(&empty_loop_args)
+                    .args(["--smbench-timer", &timer_value])
+                    .args(total_time_args(config, deadline))
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .expect("Failed to spawn child process");
+
+                let mut running = None;
+                let mut finished = false;
+                let mut terminated = false;
+                let mut progressed = false;
+                let mut mismatched = false;
+
+                let stdout = BufReader::new(child.stdout.take().unwrap());
+                for line in stdout.lines() {
+                    let line = line.unwrap();
+                    match parse_raw_line(&line) {
+                        Some(RawEvent::Start {
+                            group,
+                            name,
+                            version,
+                        }) if version != PROTOCOL_VERSION => {
+                            // the executable was replaced since this process
+                            // started, its results cannot be decoded
+                            let message = format!(
+                                "child process uses protocol version {} instead of {}",
+                                version, PROTOCOL_VERSION
+                            );
+                            results
+                                .entry((group, name))
+                                .or_default()
+                                .push(Err(Error::ProcessFailed(message)));
+                            mismatched = true;
+                            let _ = child.kill();
+                            break;
+                        }
+                        Some(RawEvent::Start { group, name, .. }) => {
+                            running = Some((group, name));
+                            started += 1;
+                            progressed = true;
+                        }
+                        Some(RawEvent::Complete {
+                            group,
+                            name,
+                            result,
+                        }) => {
+                            running = None;
+                            results.entry((group, name)).or_default().push(result);
+                        }
+                        Some(RawEvent::Terminated) => terminated = true,
+                        Some(RawEvent::Finish) => finished = true,
+                        // output from the benchmark itself
+                        None => eprintln!("{}", line),
+                    }
+                }
+
+                let status = child.wait().unwrap();
+
+                // the child process crashed while running a benchmark
+                if let Some(key) = running {
+                    let message = format!("child process exited with {}", status);
+                    results
+                        .entry(key)
+                        .or_default()
+                        .push(Err(Error::ProcessFailed(message)));
+                }
+
+                if signal::interrupted() || mismatched {
+                    break 'processes;
+                }
+
+                if (finished && !terminated && !config.isolate) || !progressed {
+                    break;
+                }
+            }
+        }
+
+        Self { processes, results }
+    }
+
+    /// Take the merged result of the benchmark. Returns the first error if the
+    /// benchmark failed in any process.
+    ///
+    /// If benchmarks were interrupted by Ctrl-C, results measured by fewer
+    /// processes are accepted.
+    pub fn take(&mut self, group: &str, name: &str) -> Result<BenchmarkResult, Error> {
+        let key = (group.to_owned(), name.to_owned());
+        let results = self.results.remove(&key).unwrap_or_default();
+        let results = results.into_iter().collect::<Result<Vec<_>, _>>()?;
+
+        if signal::interrupted() {
+            if results.is_empty() {
+                return Err(Error::Interrupted);
+            }
+        } else if results.len() < self.processes {
+            return Err(Error::ProcessFailed(format!(
+                "{} of {} processes did not report the result",
+                self.processes - results.len(),
+                self.processes
+            )));
+        }
+
+        Ok(BenchmarkResult::merge(results))
+    }
+}
+
+/// Arguments which give the rest of the time budget of the process to the
+/// child process
+fn total_time_args(config: &BenchmarkConfig, deadline: Instant) -> Vec<String> {
+    if config.total_time <= 0.0 {
+        return Vec::new();
+    }
+
+    // zero would disable the time budget
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let remaining = f64::max(remaining.as_secs_f64(), 1e-3);
+    vec!["--total-time".to_owned(), remaining.to_string()]
+}
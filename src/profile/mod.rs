@@ -0,0 +1,82 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::common::create_output_dir;
+
+mod sampler;
+mod symbols;
+
+pub(crate) use sampler::Sampler;
+
+/// Backtraces sampled while a benchmark was profiled. Identical backtraces are
+/// counted once.
+#[derive(Debug, Default)]
+pub(crate) struct Profile {
+    /// instruction pointers of each distinct backtrace, innermost frame first
+    stacks: HashMap<Vec<usize>, usize>,
+    /// samples which did not fit in the buffer
+    dropped: usize,
+}
+
+impl Profile {
+    /// Number of recorded samples
+    pub fn samples(&self) -> usize {
+        self.stacks.values().sum()
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Write the backtraces in the collapsed stack format of `stackcollapse`
+    /// (frames from the outermost one separated by `;`, followed by the number
+    /// of samples), which flame graph tools read
+    pub fn write_folded(&self, path: &Path) -> io::Result<()> {
+        // return addresses point to the instruction after the call
+        let frames = |stack: &[usize]| -> Vec<usize> {
+            let pc = stack.iter().take(1).copied();
+            pc.chain(stack.iter().skip(1).map(|&ip| ip - 1)).collect()
+        };
+        let symbols = symbols::Symbolizer::new(self.stacks.keys().flat_map(|s| frames(s)));
+
+        // backtraces through different instructions of the same functions
+        // fold into the same line
+        let mut lines: BTreeMap<String, usize> = BTreeMap::new();
+        for (stack, &count) in self.stacks.iter() {
+            let names: Vec<String> = frames(stack)
+                .into_iter()
+                .rev()
+                .map(|ip| symbols.resolve(ip).replace(';', ":"))
+                .collect();
+            *lines.entry(names.join(";")).or_insert(0) += count;
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        for (stack, count) in lines.iter() {
+            writeln!(file, "{} {}", stack, count)?;
+        }
+        file.flush()
+    }
+}
+
+/// Path of the profile of the benchmark in the output directory
+pub(crate) fn output_path(group: &str, name: &str) -> Option<PathBuf> {
+    let sanitize = |s: &str| -> String {
+        s.chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+                _ => '_',
+            })
+            .collect()
+    };
+
+    let mut path = create_output_dir()?;
+    path.push("profiles");
+    path.push(format!("{}-{}.folded", sanitize(group), sanitize(name)));
+    Some(path)
+}
@@ -0,0 +1,299 @@
+use std::io;
+
+use super::symbols;
+use super::Profile;
+
+/// Interval of CPU time between samples in microseconds
+const SAMPLE_INTERVAL_US: i64 = 1000;
+
+/// Maximum number of frames recorded per sample
+const MAX_DEPTH: usize = 128;
+
+/// Samples which fit in the buffer in addition to the expected number
+const EXTRA_SAMPLES: usize = 1000;
+
+/// Profiler which records the stack of the running thread whenever the
+/// process has used `SAMPLE_INTERVAL_US` of CPU time (`ITIMER_PROF`).
+///
+/// The `SIGPROF` handler only follows the chain of frame pointers into a
+/// buffer allocated in advance, which is async-signal-safe, and the addresses
+/// are resolved to symbols after sampling. Callers are found only through
+/// functions which keep frame pointers, so build benchmarks with
+/// `RUSTFLAGS="-C force-frame-pointers=yes"` for complete stacks. Only one
+/// sampler may exist at a time.
+pub(crate) struct Sampler {
+    buffer: Vec<usize>,
+    capacity: usize,
+}
+
+impl Sampler {
+    /// Install the signal handler and allocate space for `seconds` of samples
+    pub fn new(seconds: f64) -> io::Result<Self> {
+        let capacity = (seconds * 1e6 / SAMPLE_INTERVAL_US as f64) as usize + EXTRA_SAMPLES;
+        imp::install()?;
+        Ok(Sampler {
+            buffer: vec![0; capacity * (MAX_DEPTH + 1)],
+            capacity,
+        })
+    }
+
+    /// Start taking samples of the calling thread. Samples of other threads
+    /// record only the interrupted function.
+    pub fn start(&mut self) {
+        // frame pointers are followed only within the stack mapped so far
+        let local = 0u8;
+        let addr = &local as *const u8 as usize;
+        let stack = symbols::mappings()
+            .into_iter()
+            .find(|m| m.start <= addr && addr < m.end)
+            .map_or((0, 0), |m| (m.start, m.end));
+
+        unsafe { imp::start(self.buffer.as_mut_ptr(), self.capacity, stack) }
+    }
+
+    /// Stop taking samples and return the recorded stacks
+    pub fn stop(&mut self) -> Profile {
+        let taken = unsafe { imp::stop() };
+
+        let mut profile = Profile {
+            dropped: taken.saturating_sub(self.capacity),
+            ..Profile::default()
+        };
+        for slot in self.buffer.chunks(MAX_DEPTH + 1).take(taken) {
+            let depth = slot[0];
+            if depth > 0 {
+                *profile.stacks.entry(slot[1..=depth].to_vec()).or_insert(0) += 1;
+            }
+        }
+        profile
+    }
+}
+
+/// The handler reads the registers from the `ucontext_t` at the offsets of the
+/// signal frame of the Linux kernel, and `sigaction` is declared in the layout
+/// shared by glibc and musl, so other targets fall back to the stub below.
+#[cfg(all(
+    target_os = "linux",
+    any(target_env = "gnu", target_env = "musl"),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod imp {
+    use std::io;
+    use std::mem;
+    use std::os::raw::{c_int, c_long, c_void};
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+    use std::sync::Once;
+
+    use super::{MAX_DEPTH, SAMPLE_INTERVAL_US};
+
+    const SIGPROF: c_int = 27;
+    const SA_SIGINFO: c_int = 4;
+    const SA_RESTART: c_int = 0x1000_0000;
+    const ITIMER_PROF: c_int = 2;
+
+    static BUFFER: AtomicPtr<usize> = AtomicPtr::new(ptr::null_mut());
+    static CAPACITY: AtomicUsize = AtomicUsize::new(0);
+    /// address range of the stack of the sampled thread
+    static STACK_START: AtomicUsize = AtomicUsize::new(0);
+    static STACK_END: AtomicUsize = AtomicUsize::new(0);
+    /// number of samples taken, including dropped ones
+    static TAKEN: AtomicUsize = AtomicUsize::new(0);
+    /// number of handlers running on any thread
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+    /// Registers of the interrupted thread
+    struct Registers {
+        pc: usize,
+        sp: usize,
+        fp: usize,
+    }
+
+    pub(super) fn install() -> io::Result<()> {
+        static INSTALL: Once = Once::new();
+        let mut result = Ok(());
+
+        // the handler stays installed, since a pending SIGPROF would terminate
+        // the process with the default action
+        INSTALL.call_once(|| unsafe {
+            let mut action: ffi::sigaction = mem::zeroed();
+            action.sa_sigaction =
+                handler as extern "C" fn(c_int, *mut c_void, *mut c_void) as usize;
+            action.sa_flags = SA_SIGINFO | SA_RESTART;
+            if ffi::sigaction(SIGPROF, &action, ptr::null_mut()) != 0 {
+                result = Err(io::Error::last_os_error());
+            }
+        });
+        result
+    }
+
+    pub(super) unsafe fn start(buffer: *mut usize, capacity: usize, stack: (usize, usize)) {
+        TAKEN.store(0, Ordering::Relaxed);
+        BUFFER.store(buffer, Ordering::Relaxed);
+        STACK_START.store(stack.0, Ordering::Relaxed);
+        STACK_END.store(stack.1, Ordering::Relaxed);
+        CAPACITY.store(capacity, Ordering::Release);
+        set_timer(SAMPLE_INTERVAL_US);
+    }
+
+    /// Returns the number of samples taken since `start`
+    pub(super) unsafe fn stop() -> usize {
+        set_timer(0);
+        CAPACITY.store(0, Ordering::SeqCst);
+        while RUNNING.load(Ordering::SeqCst) > 0 {
+            std::hint::spin_loop();
+        }
+        BUFFER.store(ptr::null_mut(), Ordering::Relaxed);
+        TAKEN.load(Ordering::SeqCst)
+    }
+
+    unsafe fn set_timer(interval_us: i64) {
+        let interval = ffi::timeval {
+            tv_sec: (interval_us / 1_000_000) as c_long,
+            tv_usec: (interval_us % 1_000_000) as c_long,
+        };
+        let timer = ffi::itimerval {
+            it_interval: interval,
+            it_value: interval,
+        };
+        ffi::setitimer(ITIMER_PROF, &timer, ptr::null_mut());
+    }
+
+    /// Only atomics and loads from the stack of the sampled thread are used,
+    /// which are async-signal-safe
+    extern "C" fn handler(_signum: c_int, _info: *mut c_void, context: *mut c_void) {
+        RUNNING.fetch_add(1, Ordering::SeqCst);
+        let capacity = CAPACITY.load(Ordering::SeqCst);
+        let index = TAKEN.fetch_add(1, Ordering::Relaxed);
+        if index < capacity {
+            unsafe {
+                let slot = BUFFER.load(Ordering::Relaxed).add(index * (MAX_DEPTH + 1));
+                *slot = walk(slot.add(1), &registers(context));
+            }
+        }
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Write the interrupted program counter and the return addresses found
+    /// by following the frame pointers into `frames`, and return the number
+    /// of frames.
+    ///
+    /// Each frame record (the caller's frame pointer followed by the return
+    /// address) must lie above the previous one within the stack of the
+    /// sampled thread, so that garbage in the frame pointer register (e.g. in
+    /// functions without frame pointers, or on other threads) ends the walk
+    /// instead of reading unmapped memory.
+    unsafe fn walk(frames: *mut usize, regs: &Registers) -> usize {
+        *frames = regs.pc;
+        let mut depth = 1;
+
+        let start = STACK_START.load(Ordering::Relaxed);
+        let end = STACK_END.load(Ordering::Relaxed);
+        if regs.sp < start || end <= regs.sp {
+            return depth;
+        }
+
+        let record_size = 2 * mem::size_of::<usize>();
+        let mut low = regs.sp;
+        let mut fp = regs.fp;
+        while depth < MAX_DEPTH {
+            if fp < low || end - record_size < fp || fp & (mem::align_of::<usize>() - 1) != 0 {
+                break;
+            }
+
+            let record = fp as *const usize;
+            let ret = *record.add(1);
+            if ret == 0 {
+                break;
+            }
+            *frames.add(depth) = ret;
+            depth += 1;
+
+            low = fp + record_size;
+            fp = *record;
+        }
+        depth
+    }
+
+    /// Registers saved in the `ucontext_t` passed to the handler
+    /// (`uc_mcontext.gregs` starts at offset 40)
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn registers(context: *mut c_void) -> Registers {
+        let gregs = (context as *const u8).add(40) as *const usize;
+        // REG_RIP, REG_RSP and REG_RBP
+        Registers {
+            pc: *gregs.add(16),
+            sp: *gregs.add(15),
+            fp: *gregs.add(10),
+        }
+    }
+
+    /// Registers saved in the `ucontext_t` passed to the handler
+    /// (`uc_mcontext.regs` starts at offset 184, followed by `sp` and `pc`)
+    #[cfg(target_arch = "aarch64")]
+    unsafe fn registers(context: *mut c_void) -> Registers {
+        let regs = (context as *const u8).add(184) as *const usize;
+        // x29 is the frame pointer
+        Registers {
+            pc: *regs.add(32),
+            sp: *regs.add(31),
+            fp: *regs.add(29),
+        }
+    }
+
+    #[allow(non_camel_case_types)]
+    mod ffi {
+        use std::os::raw::{c_int, c_long};
+
+        /// `struct sigaction` of glibc and musl on 64-bit Linux
+        #[repr(C)]
+        pub struct sigaction {
+            pub sa_sigaction: usize,
+            pub sa_mask: [u64; 16],
+            pub sa_flags: c_int,
+            pub sa_restorer: usize,
+        }
+
+        const _: () = assert!(std::mem::size_of::<sigaction>() == 152);
+
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct timeval {
+            pub tv_sec: c_long,
+            pub tv_usec: c_long,
+        }
+
+        #[repr(C)]
+        pub struct itimerval {
+            pub it_interval: timeval,
+            pub it_value: timeval,
+        }
+
+        extern "C" {
+            pub fn sigaction(signum: c_int, act: *const sigaction, old: *mut sigaction) -> c_int;
+            pub fn setitimer(which: c_int, new: *const itimerval, old: *mut itimerval) -> c_int;
+        }
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_env = "gnu", target_env = "musl"),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+mod imp {
+    use std::io;
+
+    pub(super) fn install() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "profiling is only supported on Linux (x86_64 and aarch64) with glibc or musl",
+        ))
+    }
+
+    pub(super) unsafe fn start(_buffer: *mut usize, _capacity: usize, _stack: (usize, usize)) {}
+
+    pub(super) unsafe fn stop() -> usize {
+        0
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Region of the address space mapped from a file, as listed in
+/// `/proc/self/maps`
+pub(super) struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    /// offset of `start` in the file
+    pub offset: usize,
+    /// path of the file, or a name such as `[stack]` for anonymous mappings
+    pub path: String,
+}
+
+/// Mappings of the current process (empty on platforms without `/proc`)
+pub(super) fn mappings() -> Vec<Mapping> {
+    let maps = match fs::read_to_string("/proc/self/maps") {
+        Ok(maps) => maps,
+        Err(_) => return Vec::new(),
+    };
+
+    maps.lines()
+        .filter_map(|line| {
+            // start-end perms offset dev inode path
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let offset = fields.nth(1)?;
+            let path = fields.nth(2).unwrap_or("");
+            Some(Mapping {
+                start: usize::from_str_radix(start, 16).ok()?,
+                end: usize::from_str_radix(end, 16).ok()?,
+                offset: usize::from_str_radix(offset, 16).ok()?,
+                path: path.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// File mapped into the process whose addresses are resolved
+struct Module {
+    path: String,
+    /// address which the addresses in the file are relative to
+    base: usize,
+}
+
+/// Resolves sampled addresses to function names after sampling, by running
+/// `addr2line` of binutils once for each mapped file.
+///
+/// Addresses which cannot be resolved (e.g. `addr2line` is not installed or
+/// the file has no symbols) are named `file+0xOFFSET` by the address in the
+/// file, which `addr2line -f -C -e FILE OFFSET` resolves later.
+pub(crate) struct Symbolizer {
+    names: HashMap<usize, String>,
+}
+
+impl Symbolizer {
+    /// Resolve all of `addrs` at once
+    pub fn new<I: IntoIterator<Item = usize>>(addrs: I) -> Self {
+        let maps = mappings();
+        let addrs: BTreeSet<usize> = addrs.into_iter().collect();
+
+        // addresses in the file of each module
+        let mut modules: HashMap<String, (Module, Vec<(usize, usize)>)> = HashMap::new();
+        let mut names = HashMap::new();
+        for &addr in addrs.iter() {
+            match find_module(&maps, addr) {
+                Some(module) => {
+                    let relative = addr - module.base;
+                    let file_name = module.path.rsplit('/').next().unwrap_or("").to_owned();
+                    names.insert(addr, format!("{}+{:#x}", file_name, relative));
+                    modules
+                        .entry(module.path.clone())
+                        .or_insert_with(|| (module, Vec::new()))
+                        .1
+                        .push((addr, relative));
+                }
+                None => {
+                    names.insert(addr, format!("{:#x}", addr));
+                }
+            }
+        }
+
+        for (module, addrs) in modules.values() {
+            let relative: Vec<usize> = addrs.iter().map(|a| a.1).collect();
+            match addr2line(&module.path, &relative) {
+                Ok(functions) => {
+                    for (&(addr, _), function) in addrs.iter().zip(functions) {
+                        if let Some(function) = function {
+                            names.insert(addr, function);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!(
+                        "Warning: failed to resolve symbols of {} by addr2line: {}",
+                        module.path, e
+                    );
+                    if e.kind() == io::ErrorKind::NotFound {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Symbolizer { names }
+    }
+
+    pub fn resolve(&self, addr: usize) -> String {
+        match self.names.get(&addr) {
+            Some(name) => name.clone(),
+            None => format!("{:#x}", addr),
+        }
+    }
+}
+
+fn find_module(maps: &[Mapping], addr: usize) -> Option<Module> {
+    let mapping = maps
+        .iter()
+        .find(|m| m.start <= addr && addr < m.end && m.path.starts_with('/'))?;
+
+    // executables not built as PIE (`ET_EXEC`) are linked at fixed addresses,
+    // other files are relative to the mapping of their start
+    let base = if is_position_independent(&mapping.path) {
+        maps.iter()
+            .filter(|m| m.path == mapping.path)
+            .map(|m| m.start.wrapping_sub(m.offset))
+            .min()?
+    } else {
+        0
+    };
+
+    Some(Module {
+        path: mapping.path.clone(),
+        base,
+    })
+}
+
+/// Whether the ELF file at `path` is a shared object or a PIE (`ET_DYN`)
+fn is_position_independent(path: &str) -> bool {
+    const ET_DYN: u16 = 3;
+
+    let mut header = [0u8; 18];
+    let read = File::open(path).and_then(|mut f| f.read_exact(&mut header));
+    if read.is_err() || &header[..4] != b"\x7fELF" {
+        return true;
+    }
+
+    // EI_DATA is 2 for big-endian files
+    let e_type = [header[16], header[17]];
+    let e_type = if header[5] == 2 {
+        u16::from_be_bytes(e_type)
+    } else {
+        u16::from_le_bytes(e_type)
+    };
+    e_type == ET_DYN
+}
+
+/// Run `addr2line` on `addrs` in the file at `path` and return the demangled
+/// function name of each address, or `None` if it is unknown
+fn addr2line(path: &str, addrs: &[usize]) -> io::Result<Vec<Option<String>>> {
+    let mut child = Command::new("addr2line")
+        .args(["-f", "-C", "-e", path])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    // write from another thread, since addr2line writes while reading
+    let mut stdin = child.stdin.take().unwrap();
+    let input: String = addrs.iter().map(|a| format!("{:#x}\n", a)).collect();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    // each address is answered by a line of the function and a line of the
+    // source location
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let functions = stdout
+        .lines()
+        .step_by(2)
+        .map(|line| line.map(|f| Some(f).filter(|f| f != "??")))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    writer.join().unwrap()?;
+    let status = child.wait()?;
+    if !status.success() || functions.len() != addrs.len() {
+        let message = format!("addr2line exited with {}", status);
+        return Err(io::Error::other(message));
+    }
+    Ok(functions)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn module_of_function() {
+        let addr = module_of_function as fn() as usize;
+        let module = find_module(&mappings(), addr).unwrap();
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(Path::new(&module.path), exe);
+        assert!(module.base <= addr);
+    }
+}
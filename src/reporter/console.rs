@@ -2,13 +2,17 @@ use std::cell::Cell;
 use std::io::Write;
 
 use super::{Reporter, ReporterOptions};
+use crate::error::Error;
 use crate::fmt;
+use crate::measurement::Unit;
 use crate::stats::Distribution;
 use crate::summary;
 use crate::{BenchmarkGroup, BenchmarkInfo, BenchmarkResult};
 
 pub struct ConsoleReporter {
     name_width_max: Cell<usize>,
+    failures: Cell<usize>,
+    header_printed: Cell<bool>,
 }
 
 impl ConsoleReporter {
@@ -32,22 +36,50 @@ impl ConsoleReporter {
 
         ConsoleReporter {
             name_width_max: Cell::new(9),
+            failures: Cell::new(0),
+            header_printed: Cell::new(false),
         }
     }
 }
 
 impl Reporter for ConsoleReporter {
     fn on_group_init(&self, group: &BenchmarkGroup, options: &ReporterOptions) {
+        if !self.header_printed.replace(true) {
+            // the seed is printed only if it was changed by --seed
+            if options.seed != 0 {
+                println!("Random Seed: {}", options.seed);
+            }
+            println!(
+                "Timer Resolution: {} (overhead: {})",
+                fmt::time(options.timer_resolution),
+                fmt::time(options.timer_overhead)
+            );
+            if let Some(frequency) = options.tsc_frequency {
+                println!("TSC Frequency: {:.3} GHz", frequency * 1e-9);
+            }
+        }
+
         if let Some(w) = group.benchmarks().iter().map(|b| b.name().len()).max() {
             if w > 9 {
                 self.name_width_max.set(w);
             }
         }
 
-        println!("\n# {} ({})", group.name(), group.file());
+        let label = if options.cache_cold {
+            " [cold cache]"
+        } else {
+            ""
+        };
+        println!("\n# {} ({}){}", group.name(), group.file(), label);
+        let label = if options.measurement == "time" {
+            "Time"
+        } else {
+            &*options.measurement
+        };
         println!(
-            "Benchmark{}        Time                 {:>3.0}% CI",
+            "Benchmark{}        {:<21}{:>3.0}% CI",
             " ".repeat(self.name_width_max.get().saturating_sub(9)),
+            label,
             options.confidence_level * 100.0
         );
         println!("{}", "-".repeat(self.name_width_max.get() + 36));
@@ -65,23 +97,129 @@ impl Reporter for ConsoleReporter {
         options: &ReporterOptions,
     ) {
         let padding = self.name_width_max.get() - info.name().len();
-        let summ = summary::summarize(result);
-        let mean = summ.elapsed_time.mean();
+        let summ = summary::summarize(result, options.subtract_overhead);
+        let unit = options.unit;
 
+        // open-loop mode reports only the latency at each rate below
         let margin = (1.0 - options.confidence_level) * 0.5;
-        let confidence_interval = (
-            summ.elapsed_time.icdf(margin),
-            summ.elapsed_time.icdf(1.0 - margin),
-        );
+        if let Some(ref elapsed_time) = summ.elapsed_time {
+            print!(
+                "{}{}  [{}, {}]",
+                " ".repeat(padding),
+                options.format_value(elapsed_time.mean()),
+                options.format_value(elapsed_time.icdf(margin)),
+                options.format_value(elapsed_time.icdf(1.0 - margin))
+            );
+        }
 
-        print!(
-            "{}{}  [{}, {}]",
-            " ".repeat(padding),
-            fmt::time(mean),
-            fmt::time(confidence_interval.0),
-            fmt::time(confidence_interval.1)
-        );
+        let mut components = Vec::new();
+        if let Some(ref c) = summ.processes {
+            let within = options.format_value(c.within.sqrt());
+            let between = options.format_value(c.between.sqrt());
+            components.push(format!("within-run σ: {}", within));
+            components.push(format!("between-run σ: {}", between));
+        }
+        if let Some(ref c) = summ.layouts {
+            let between = options.format_value(c.between.sqrt());
+            components.push(format!("between-layout σ: {}", between));
+        }
+        if let Some(ref wall) = summ.wall_time {
+            components.push(format!("wall time: {}", fmt::time(wall.mean())));
+            if let (Unit::Seconds, Some(ref t)) = (unit, &summ.elapsed_time) {
+                // wall-clock time includes the loop overhead
+                let cpu = t.mean() + summ.overhead.as_ref().map_or(0.0, |o| o.mean());
+                let ratio = cpu / wall.mean();
+                if options.measurement == "process-cpu-time" {
+                    // CPU time of several threads may exceed wall-clock time
+                    components.push(format!("CPU/wall: {:.2}", ratio));
+                } else {
+                    let off_cpu = (1.0 - ratio).clamp(0.0, 1.0);
+                    components.push(format!("off-CPU: {:.1}%", off_cpu * 100.0));
+                }
+            }
+        }
+        if let Some(ref o) = summ.overhead {
+            let overhead = options.format_value(o.mean());
+            components.push(format!("loop overhead: {} subtracted", overhead));
+        }
+        if let Some(n) = result.input_pool {
+            components.push(format!("input pool: {}", n));
+        }
+        if let Some(bytes) = result.stack {
+            components.push(format!("max stack: {}", fmt::value(bytes as f64, Unit::Bytes)));
+        }
+        if let Some(first) = summ.first_calls.first() {
+            components.push(format!("first call: {}", options.format_value(*first)));
+        }
+        if summ.first_calls.len() > 1 {
+            let k = summ.first_calls.len();
+            let mean = summ.first_calls.iter().sum::<f64>() / k as f64;
+            components.push(format!("first {} calls: {} avg", k, options.format_value(mean)));
+        }
+        if !components.is_empty() {
+            print!("  ({})", components.join(", "));
+        }
 
         println!();
+        if !summ.latency.is_empty() {
+            let percentiles: Vec<String> = summ
+                .latency
+                .iter()
+                .map(|(label, v)| format!("{}: {}", label, options.format_value(*v)))
+                .collect();
+            println!(
+                "{}{}",
+                " ".repeat(self.name_width_max.get() + 3),
+                percentiles.join(", ")
+            );
+        }
+        for rate in summ.rates.iter() {
+            let percentiles: Vec<String> = rate
+                .latency
+                .iter()
+                .map(|(label, v)| format!("{}: {}", label, fmt::time(*v)))
+                .collect();
+            println!(
+                "{}{}/s: {}  (throughput: {}/s{})",
+                " ".repeat(self.name_width_max.get() + 3),
+                fmt::value(rate.rate, Unit::Count),
+                percentiles.join(", "),
+                fmt::value(rate.throughput, Unit::Count),
+                if rate.saturated { ", saturated" } else { "" }
+            );
+        }
+        let counters = summ
+            .counters
+            .iter()
+            .map(|(name, c)| format!("{}: {}", name, fmt::value(c.mean(), Unit::Count)));
+        let metrics = summ
+            .metrics
+            .iter()
+            .map(|(name, unit, m)| format!("{}: {}", name, fmt::value(m.mean(), *unit)));
+        let values: Vec<String> = counters.chain(metrics).collect();
+        if !values.is_empty() {
+            println!(
+                "{}{}",
+                " ".repeat(self.name_width_max.get() + 3),
+                values.join(", ")
+            );
+        }
+        if let Some(warning) = summ.warning {
+            println!("{}WARNING: {}", " ".repeat(self.name_width_max.get() + 3), warning);
+        }
+    }
+
+    fn on_benchmark_failed(&self, info: &BenchmarkInfo, error: &Error, _options: &ReporterOptions) {
+        let padding = self.name_width_max.get() - info.name().len();
+        println!("{}{}", " ".repeat(padding), error);
+        self.failures.set(self.failures.get() + 1);
+    }
+
+    fn on_finish(&self, _options: &ReporterOptions) {
+        match self.failures.get() {
+            0 => {}
+            1 => println!("\n1 benchmark failed"),
+            n => println!("\n{} benchmarks failed", n),
+        }
     }
 }
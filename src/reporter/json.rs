@@ -1,26 +1,71 @@
 use std::cell::RefCell;
-use std::io::Write;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
 use serde::ser::{Serialize, Serializer, SerializeStruct};
+use serde_json::{Map, Value};
 
 use super::{Reporter, ReporterOptions};
 use crate::stats::Distribution;
 use crate::summary;
 use crate::{BenchmarkGroup, BenchmarkInfo, BenchmarkResult};
 use crate::common::create_output_dir;
+use crate::measurement::Unit;
+use crate::error::Error;
 
 struct BenchmarkRecords {
+    shard: Option<(usize, usize)>,
+    seed: u64,
+    /// resolution and overhead of the timer
+    timer: (f64, f64),
+    /// name and unit of the measured quantity
+    measurement: (String, Unit),
+    tsc_frequency: Option<f64>,
     groups: Vec<GroupBenchmarkRecord>
 }
 
 struct GroupBenchmarkRecord {
     name: String,
-    benchmarks: Vec<BenchmarkRecord>
+    benchmarks: Vec<BenchmarkRecord>,
+    failures: Vec<FailureRecord>,
 }
 
 struct BenchmarkRecord {
     name: String,
-    mean: f64,
-    confidence_interval: (f64, f64),
+    /// mean and confidence interval (not estimated in open-loop mode)
+    mean: Option<f64>,
+    confidence_interval: Option<(f64, f64)>,
+    /// standard deviations within and between processes
+    processes: Option<(f64, f64)>,
+    /// standard deviation between randomized memory layouts
+    between_layout_stddev: Option<f64>,
+    /// mean time of the first calls measured before warm-up
+    first_calls: Vec<f64>,
+    /// whether CPU caches were evicted before every iteration
+    cache_cold: bool,
+    /// number of inputs passed to `Bencher::iter_with_inputs`
+    input_pool_size: Option<usize>,
+    /// maximum stack depth in bytes measured with `--measure-stack`
+    max_stack: Option<usize>,
+    /// why the result is probably meaningless
+    warning: Option<String>,
+    /// time of an empty loop subtracted from `mean`
+    subtracted_overhead: Option<f64>,
+    /// wall-clock time per iteration if something else is measured
+    wall_time: Option<f64>,
+    /// performance counters and reported counters per iteration
+    counters: Map<String, Value>,
+    /// mean and unit of the metrics reported by the benchmark
+    metrics: Map<String, Value>,
+    /// percentiles and histogram of single calls in latency mode
+    latency: Option<Map<String, Value>>,
+    /// throughput and latency percentiles at each rate in open-loop mode
+    rates: Vec<Value>,
+}
+
+struct FailureRecord {
+    name: String,
+    error: String,
 }
 
 impl Serialize for BenchmarkRecords {
@@ -29,6 +74,19 @@ impl Serialize for BenchmarkRecords {
         S: Serializer
     {
         let mut s = serializer.serialize_struct("BenchmarkRecords", 1)?;
+        match self.shard {
+            Some(ref shard) => s.serialize_field("shard", shard)?,
+            None => s.skip_field("shard")?,
+        }
+        s.serialize_field("seed", &self.seed)?;
+        s.serialize_field("timer_resolution", &self.timer.0)?;
+        s.serialize_field("timer_overhead", &self.timer.1)?;
+        s.serialize_field("measurement", &self.measurement.0)?;
+        s.serialize_field("unit", self.measurement.1.as_str())?;
+        match self.tsc_frequency {
+            Some(ref frequency) => s.serialize_field("tsc_frequency", frequency)?,
+            None => s.skip_field("tsc_frequency")?,
+        }
         s.serialize_field("groups", &self.groups)?;
         s.end()
     }
@@ -42,6 +100,11 @@ impl Serialize for GroupBenchmarkRecord {
         let mut s = serializer.serialize_struct("GroupBenchmarkRecord", 1)?;
         s.serialize_field("name", &self.name)?;
         s.serialize_field("benchmarks", &self.benchmarks)?;
+        if self.failures.is_empty() {
+            s.skip_field("failures")?;
+        } else {
+            s.serialize_field("failures", &self.failures)?;
+        }
         s.end()
     }
 }
@@ -53,8 +116,86 @@ impl Serialize for BenchmarkRecord {
     {
         let mut s = serializer.serialize_struct("BenchmarkRecord", 1)?;
         s.serialize_field("name", &self.name)?;
-        s.serialize_field("mean", &self.mean)?;
-        s.serialize_field("confidence_interval", &self.confidence_interval)?;
+        match self.mean {
+            Some(ref mean) => s.serialize_field("mean", mean)?,
+            None => s.skip_field("mean")?,
+        }
+        match self.confidence_interval {
+            Some(ref interval) => s.serialize_field("confidence_interval", interval)?,
+            None => s.skip_field("confidence_interval")?,
+        }
+        if let Some((within, between)) = self.processes {
+            s.serialize_field("within_run_stddev", &within)?;
+            s.serialize_field("between_run_stddev", &between)?;
+        } else {
+            s.skip_field("within_run_stddev")?;
+            s.skip_field("between_run_stddev")?;
+        }
+        match self.between_layout_stddev {
+            Some(ref stddev) => s.serialize_field("between_layout_stddev", stddev)?,
+            None => s.skip_field("between_layout_stddev")?,
+        }
+        if self.first_calls.is_empty() {
+            s.skip_field("first_calls")?;
+        } else {
+            s.serialize_field("first_calls", &self.first_calls)?;
+        }
+        if self.cache_cold {
+            s.serialize_field("cache", "cold")?;
+        } else {
+            s.skip_field("cache")?;
+        }
+        match self.input_pool_size {
+            Some(ref n) => s.serialize_field("input_pool_size", n)?,
+            None => s.skip_field("input_pool_size")?,
+        }
+        match self.max_stack {
+            Some(ref n) => s.serialize_field("max_stack", n)?,
+            None => s.skip_field("max_stack")?,
+        }
+        match self.warning {
+            Some(ref warning) => s.serialize_field("warning", warning)?,
+            None => s.skip_field("warning")?,
+        }
+        match self.wall_time {
+            Some(ref time) => s.serialize_field("wall_time", time)?,
+            None => s.skip_field("wall_time")?,
+        }
+        if self.counters.is_empty() {
+            s.skip_field("counters")?;
+        } else {
+            s.serialize_field("counters", &self.counters)?;
+        }
+        if self.metrics.is_empty() {
+            s.skip_field("metrics")?;
+        } else {
+            s.serialize_field("metrics", &self.metrics)?;
+        }
+        match self.latency {
+            Some(ref latency) => s.serialize_field("latency", latency)?,
+            None => s.skip_field("latency")?,
+        }
+        if self.rates.is_empty() {
+            s.skip_field("rates")?;
+        } else {
+            s.serialize_field("rates", &self.rates)?;
+        }
+        match self.subtracted_overhead {
+            Some(ref overhead) => s.serialize_field("subtracted_overhead", overhead)?,
+            None => s.skip_field("subtracted_overhead")?,
+        }
+        s.end()
+    }
+}
+
+impl Serialize for FailureRecord {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("FailureRecord", 2)?;
+        s.serialize_field("name", &self.name)?;
+        s.serialize_field("error", &self.error)?;
         s.end()
     }
 }
@@ -66,7 +207,14 @@ pub struct JsonReporter {
 impl JsonReporter {
     pub fn new() -> Self {
         Self {
-            data: RefCell::new(BenchmarkRecords { groups: Vec::new() })
+            data: RefCell::new(BenchmarkRecords {
+                shard: None,
+                seed: 0,
+                timer: (0.0, 0.0),
+                measurement: (String::new(), Unit::Seconds),
+                tsc_frequency: None,
+                groups: Vec::new(),
+            })
         }
     }
 }
@@ -75,7 +223,8 @@ impl Reporter for JsonReporter {
     fn on_group_init(&self, group: &BenchmarkGroup, _options: &ReporterOptions) {
         let new_entry = GroupBenchmarkRecord {
             name: group.name().to_owned(),
-            benchmarks: Vec::new()
+            benchmarks: Vec::new(),
+            failures: Vec::new(),
         };
         self.data.borrow_mut().groups.push(new_entry);
     }
@@ -86,25 +235,99 @@ impl Reporter for JsonReporter {
         result: &BenchmarkResult,
         options: &ReporterOptions,
     ) {
-        let summ = summary::summarize(result);
-        let mean = summ.elapsed_time.mean();
+        let summ = summary::summarize(result, options.subtract_overhead);
+        let mean = summ.elapsed_time.as_ref().map(|t| t.mean());
 
         let margin = (1.0 - options.confidence_level) * 0.5;
-        let confidence_interval = (
-            summ.elapsed_time.icdf(margin),
-            summ.elapsed_time.icdf(1.0 - margin),
-        );
+        let confidence_interval = summ
+            .elapsed_time
+            .as_ref()
+            .map(|t| (t.icdf(margin), t.icdf(1.0 - margin)));
+
+        let percentiles = &summ.latency;
+        let latency = result.latency.as_ref().map(|histogram| {
+            let mut latency: Map<String, Value> = percentiles
+                .iter()
+                .map(|&(label, v)| (label.to_owned(), Value::from(v)))
+                .collect();
+            latency.insert("count".to_owned(), Value::from(histogram.count()));
+            // lower bound and count of each bucket
+            let buckets = histogram
+                .buckets()
+                .map(|(start, count)| Value::from(vec![Value::from(start), Value::from(count)]))
+                .collect();
+            latency.insert("histogram".to_owned(), Value::Array(buckets));
+            latency
+        });
+
+        let rates = summ
+            .rates
+            .iter()
+            .map(|r| {
+                let mut rate = Map::new();
+                rate.insert("rate".to_owned(), Value::from(r.rate));
+                rate.insert("throughput".to_owned(), Value::from(r.throughput));
+                rate.insert("saturated".to_owned(), Value::from(r.saturated));
+                for &(label, v) in r.latency.iter() {
+                    rate.insert(label.to_owned(), Value::from(v));
+                }
+                Value::Object(rate)
+            })
+            .collect();
 
         let new_entry = BenchmarkRecord {
             name: info.name().to_owned(),
             mean,
             confidence_interval,
+            processes: summ
+                .processes
+                .map(|c| (c.within.sqrt(), c.between.sqrt())),
+            between_layout_stddev: summ.layouts.map(|c| c.between.sqrt()),
+            first_calls: summ.first_calls,
+            cache_cold: options.cache_cold,
+            input_pool_size: result.input_pool,
+            max_stack: result.stack,
+            warning: summ.warning.map(|w| w.to_string()),
+            subtracted_overhead: summ.overhead.map(|o| o.mean()),
+            wall_time: summ.wall_time.map(|t| t.mean()),
+            counters: summ
+                .counters
+                .iter()
+                .map(|(name, c)| (name.clone(), Value::from(c.mean())))
+                .collect(),
+            metrics: summ
+                .metrics
+                .iter()
+                .map(|(name, unit, m)| {
+                    let mut metric = Map::new();
+                    metric.insert("value".to_owned(), Value::from(m.mean()));
+                    metric.insert("unit".to_owned(), Value::from(unit.as_str()));
+                    (name.clone(), Value::Object(metric))
+                })
+                .collect(),
+            latency,
+            rates,
         };
 
         self.data.borrow_mut().groups.last_mut().unwrap().benchmarks.push(new_entry);
     }
 
-    fn on_finish(&self, _options: &ReporterOptions) {
+    fn on_benchmark_failed(&self, info: &BenchmarkInfo, error: &Error, _options: &ReporterOptions) {
+        let new_entry = FailureRecord {
+            name: info.name().to_owned(),
+            error: error.to_string(),
+        };
+
+        self.data.borrow_mut().groups.last_mut().unwrap().failures.push(new_entry);
+    }
+
+    fn on_finish(&self, options: &ReporterOptions) {
+        self.data.borrow_mut().shard = options.shard;
+        self.data.borrow_mut().seed = options.seed;
+        self.data.borrow_mut().timer = (options.timer_resolution, options.timer_overhead);
+        self.data.borrow_mut().measurement = (options.measurement.clone(), options.unit);
+        self.data.borrow_mut().tsc_frequency = options.tsc_frequency;
+
         let mut path = create_output_dir().expect("Failed to detect 'target_dir'");
         path.push("benchmark.json");
         let mut file = std::fs::File::create(&path).unwrap();
@@ -113,3 +336,177 @@ impl Reporter for JsonReporter {
         self.data.borrow_mut().groups.clear();
     }
 }
+
+/// Merge JSON reports written by the shards of the benchmark suite into
+/// `output`. Groups with the same name are merged into one group, and the
+/// merged shards are listed in `shards`.
+///
+/// A warning is printed if some shards are missing or given more than once, or
+/// were measured with different seeds. Reports of different measurements
+/// (e.g. wall-clock time and cycles) cannot be merged.
+pub fn merge_reports<P: AsRef<Path>>(inputs: &[P], output: &Path) -> io::Result<()> {
+    let mut groups: Vec<Map<String, Value>> = Vec::new();
+    let mut shards = Vec::new();
+    let mut header = Map::new();
+
+    for input in inputs {
+        let report: Value = serde_json::from_str(&fs::read_to_string(input)?)?;
+
+        if let Some(shard) = report.get("shard").and_then(Value::as_array) {
+            let shard: Option<Vec<u64>> = shard.iter().map(Value::as_u64).collect();
+            shards.extend(shard);
+        }
+
+        if merge_header_field(&mut header, &report, "seed").is_some() {
+            eprintln!(
+                "Warning: {} was measured with a different seed",
+                input.as_ref().display()
+            );
+        }
+        // the timer and the TSC are calibrated by each shard, the first
+        // calibration is kept
+        merge_header_field(&mut header, &report, "timer_resolution");
+        merge_header_field(&mut header, &report, "timer_overhead");
+        merge_header_field(&mut header, &report, "tsc_frequency");
+        for field in &["measurement", "unit"] {
+            if let Some(value) = merge_header_field(&mut header, &report, field) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} was measured with {} {} instead of {}",
+                        input.as_ref().display(),
+                        field,
+                        value,
+                        header[*field]
+                    ),
+                ));
+            }
+        }
+
+        let input_groups = report
+            .get("groups")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid_report(input.as_ref()))?;
+
+        for group in input_groups {
+            let group = group
+                .as_object()
+                .ok_or_else(|| invalid_report(input.as_ref()))?;
+            let name = group.get("name");
+
+            match groups.iter_mut().find(|g| g.get("name") == name) {
+                Some(merged) => {
+                    for key in &["benchmarks", "failures"] {
+                        let records = match group.get(*key) {
+                            Some(Value::Array(records)) => records,
+                            _ => continue,
+                        };
+
+                        match merged.get_mut(*key) {
+                            Some(Value::Array(merged_records)) => {
+                                merged_records.extend(records.iter().cloned())
+                            }
+                            _ => {
+                                merged.insert(key.to_string(), Value::Array(records.clone()));
+                            }
+                        }
+                    }
+                }
+                None => groups.push(group.clone()),
+            }
+        }
+    }
+
+    if let Some(count) = shards.first().map(|s| s[1]) {
+        for index in 1..=count {
+            let n = shards.iter().filter(|s| **s == [index, count]).count();
+            if n != 1 {
+                eprintln!("Warning: shard {}/{} is found in {} report(s)", index, count, n);
+            }
+        }
+    }
+
+    let mut merged = Map::new();
+    if !shards.is_empty() {
+        let shards = shards.iter().map(|s| serde_json::json!(s)).collect();
+        merged.insert("shards".to_owned(), Value::Array(shards));
+    }
+    merged.extend(header);
+    let groups = groups.into_iter().map(Value::Object).collect();
+    merged.insert("groups".to_owned(), Value::Array(groups));
+    let mut file = fs::File::create(output)?;
+    write!(file, "{}", serde_json::to_string_pretty(&merged)?)?;
+    Ok(())
+}
+
+/// Copy `field` of `report` into the merged `header` unless it is already
+/// there, and return the value of `report` if it differs from the merged one
+fn merge_header_field<'a>(
+    header: &mut Map<String, Value>,
+    report: &'a Value,
+    field: &str,
+) -> Option<&'a Value> {
+    let value = report.get(field)?;
+    match header.get(field) {
+        Some(merged) if merged != value => Some(value),
+        Some(_) => None,
+        None => {
+            header.insert(field.to_owned(), value.clone());
+            None
+        }
+    }
+}
+
+fn invalid_report(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} is not a report of smbench", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_report(name: &str, report: Value) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("smbench-{}-{}", std::process::id(), name));
+        fs::write(&path, report.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn merge_shards() {
+        let header = |shard: u64, measurement: &str| {
+            serde_json::json!({
+                "shard": [shard, 2],
+                "seed": 0,
+                "timer_resolution": 1e-9,
+                "timer_overhead": 2e-8 * shard as f64,
+                "measurement": measurement,
+                "unit": "s",
+                "tsc_frequency": 3e9,
+                "groups": [{"name": "g", "benchmarks": [{"name": format!("b{}", shard)}]}],
+            })
+        };
+        let first = write_report("1.json", header(1, "time"));
+        let second = write_report("2.json", header(2, "time"));
+        let cpu_time = write_report("3.json", header(2, "cpu-time"));
+        let output = write_report("merged.json", Value::Null);
+
+        merge_reports(&[&first, &second], &output).unwrap();
+        let merged: Value = serde_json::from_str(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(merged["shards"], serde_json::json!([[1, 2], [2, 2]]));
+        assert_eq!(merged["seed"], 0);
+        assert_eq!(merged["timer_overhead"], 2e-8);
+        assert_eq!(merged["measurement"], "time");
+        assert_eq!(merged["tsc_frequency"], 3e9);
+        assert_eq!(merged["groups"][0]["benchmarks"].as_array().unwrap().len(), 2);
+
+        // wall-clock time and CPU time cannot be merged
+        assert!(merge_reports(&[&first, &cpu_time], &output).is_err());
+
+        for path in &[first, second, cpu_time, output] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
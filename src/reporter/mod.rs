@@ -1,6 +1,12 @@
 mod console;
 pub use console::*;
 
+mod raw;
+pub(crate) use raw::{
+    format_error_line, format_result_line, parse_raw_line, RawEvent, PROTOCOL_VERSION,
+};
+pub use raw::RawReporter;
+
 #[cfg(feature = "json")]
 mod json;
 #[cfg(feature = "json")]
@@ -9,17 +15,88 @@ pub use json::*;
 use crate::bench::BenchmarkResult;
 use crate::common::{BenchmarkGroup, BenchmarkInfo};
 use crate::config::BenchmarkConfig;
+use crate::error::Error;
+use crate::measurement::{Measurement, Unit};
+
+use std::fmt;
+use std::sync::Arc;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct ReporterOptions {
     pub confidence_level: f64,
+    pub shard: Option<(usize, usize)>,
+    /// whether CPU caches were evicted before every iteration
+    pub cache_cold: bool,
+    /// seed of the random number generator returned by `Bencher::rng()`
+    pub seed: u64,
+    /// resolution of the timer and the cost of reading it twice in seconds
+    pub timer_resolution: f64,
+    pub timer_overhead: f64,
+    /// whether the time of an empty loop is subtracted from the results
+    pub subtract_overhead: bool,
+    /// name and unit of the measured quantity
+    pub measurement: String,
+    pub unit: Unit,
+    /// frequency of the time stamp counter in Hz, if cycles are measured
+    pub tsc_frequency: Option<f64>,
+    /// measurement set by `App::set_measurement`, which formats the values
+    /// instead of `unit`
+    pub format: Option<MeasurementFormat>,
 }
 
 impl ReporterOptions {
     pub fn from_config(config: &BenchmarkConfig) -> Self {
         ReporterOptions {
             confidence_level: config.confidence_level,
+            shard: config.shard,
+            cache_cold: config.cache_cold,
+            seed: config.seed,
+            timer_resolution: 0.0,
+            timer_overhead: 0.0,
+            subtract_overhead: config.subtract_overhead,
+            measurement: "time".to_owned(),
+            unit: Unit::Seconds,
+            tsc_frequency: None,
+            format: None,
+        }
+    }
+
+    /// Format a value of the measured quantity by `Measurement::format`
+    pub fn format_value(&self, value: f64) -> String {
+        match self.format {
+            Some(ref format) => format.to_string(value),
+            None => crate::fmt::value(value, self.unit).to_string(),
+        }
+    }
+}
+
+/// Custom measurement shared with reporters to format its values
+#[derive(Clone)]
+pub struct MeasurementFormat(pub Arc<dyn Measurement + Sync>);
+
+impl MeasurementFormat {
+    fn to_string(&self, value: f64) -> String {
+        struct Value<'a>(&'a dyn Measurement, f64);
+
+        impl fmt::Display for Value<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.format(self.1, f)
+            }
         }
+
+        Value(&*self.0, value).to_string()
+    }
+}
+
+impl fmt::Debug for MeasurementFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("MeasurementFormat").field(&self.0.name()).finish()
+    }
+}
+
+impl PartialEq for MeasurementFormat {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
     }
 }
 
@@ -30,7 +107,6 @@ pub trait Reporter {
     fn on_warmup(&self, _info: &BenchmarkInfo, _options: &ReporterOptions) {}
     #[doc(hidden)]
     fn on_terminated(&self, _info: &BenchmarkInfo, _options: &ReporterOptions) {}
-    #[doc(hidden)]
     fn on_measurement_start(
         &self,
         _info: &BenchmarkInfo,
@@ -46,6 +122,13 @@ pub trait Reporter {
         _options: &ReporterOptions,
     ) {
     }
+    fn on_benchmark_failed(
+        &self,
+        _info: &BenchmarkInfo,
+        _error: &Error,
+        _options: &ReporterOptions,
+    ) {
+    }
     fn on_group_finish(&self, _group: &BenchmarkGroup, _options: &ReporterOptions) {}
     fn on_finish(&self, _options: &ReporterOptions) {}
 }
@@ -56,6 +139,10 @@ impl dyn Reporter {
             return Box::new(ConsoleReporter::new());
         }
 
+        if s == "raw" {
+            return Box::new(RawReporter::new());
+        }
+
         #[cfg(feature = "json")]
         {
             if s == "json" {
@@ -66,3 +153,43 @@ impl dyn Reporter {
         panic!("Invalid reporter name: {}", s);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone)]
+    struct Pages;
+
+    impl Measurement for Pages {
+        fn name(&self) -> &str {
+            "pages"
+        }
+
+        fn unit(&self) -> Unit {
+            Unit::Count
+        }
+
+        fn start(&mut self) -> f64 {
+            0.0
+        }
+
+        fn end(&mut self, _intermediate: f64) -> f64 {
+            0.0
+        }
+
+        fn format(&self, value: f64, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{:.1} pages", value)
+        }
+    }
+
+    #[test]
+    fn format_value() {
+        let mut options = ReporterOptions::from_config(&BenchmarkConfig::default());
+        options.unit = Unit::Count;
+        assert_eq!(options.format_value(1.25), crate::fmt::value(1.25, Unit::Count).to_string());
+
+        options.format = Some(MeasurementFormat(Arc::new(Pages)));
+        assert_eq!(options.format_value(1.25), "1.2 pages");
+    }
+}
@@ -0,0 +1,438 @@
+use std::cell::RefCell;
+use std::io::Write;
+
+use super::{Reporter, ReporterOptions};
+use crate::error::Error;
+use crate::{BenchmarkGroup, BenchmarkInfo, BenchmarkResult, RateResult};
+
+/// version of the line format, written in start lines. It must be bumped when
+/// the format of any line changes.
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+const START_PREFIX: &str = "smbench:start";
+const RESULT_PREFIX: &str = "smbench:result";
+const ERROR_PREFIX: &str = "smbench:error";
+const TERMINATED_PREFIX: &str = "smbench:terminated";
+const FINISH_PREFIX: &str = "smbench:finish";
+/// prefix of the keys of performance counters in result lines
+const COUNTER_PREFIX: &str = "counter.";
+/// prefix of the keys of metrics in result lines, followed by the unit and
+/// the name of the metric (e.g. `metric.ratio.compression_ratio`)
+const METRIC_PREFIX: &str = "metric.";
+/// prefix of the keys of open-loop results in result lines, followed by the
+/// offered rate. Values are the elapsed time followed by the histogram.
+const RATE_PREFIX: &str = "rate.";
+
+/// Reporter which writes raw measurements to stdout in a line-oriented format.
+///
+/// This is used by child processes to send their results back to the parent
+/// process.
+#[derive(Default)]
+pub struct RawReporter {
+    group: RefCell<String>,
+}
+
+impl RawReporter {
+    pub fn new() -> Self {
+        Self {
+            group: RefCell::new(String::new()),
+        }
+    }
+
+    fn write_line(&self, prefix: &str, info: Option<&BenchmarkInfo>, fields: &[&str]) {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        write!(stdout, "{}", prefix).unwrap();
+        if let Some(info) = info {
+            write!(stdout, "\t{}\t{}", self.group.borrow(), info.name()).unwrap();
+        }
+        for field in fields {
+            write!(stdout, "\t{}", field).unwrap();
+        }
+        writeln!(stdout).unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+impl Reporter for RawReporter {
+    fn on_group_init(&self, group: &BenchmarkGroup, _options: &ReporterOptions) {
+        *self.group.borrow_mut() = group.name().to_owned();
+    }
+
+    fn on_benchmark_start(&self, info: &BenchmarkInfo, _options: &ReporterOptions) {
+        self.write_line(START_PREFIX, Some(info), &[&PROTOCOL_VERSION.to_string()]);
+    }
+
+    fn on_terminated(&self, info: &BenchmarkInfo, _options: &ReporterOptions) {
+        self.write_line(TERMINATED_PREFIX, Some(info), &[]);
+    }
+
+    fn on_benchmark_complete(
+        &self,
+        info: &BenchmarkInfo,
+        result: &BenchmarkResult,
+        _options: &ReporterOptions,
+    ) {
+        let line = format_result_line(&self.group.borrow(), info.name(), result);
+
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", line).unwrap();
+        stdout.flush().unwrap();
+    }
+
+    fn on_benchmark_failed(&self, info: &BenchmarkInfo, error: &Error, _options: &ReporterOptions) {
+        let line = format_error_line(&self.group.borrow(), info.name(), error);
+
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", line).unwrap();
+        stdout.flush().unwrap();
+    }
+
+    fn on_finish(&self, _options: &ReporterOptions) {
+        self.write_line(FINISH_PREFIX, None, &[]);
+    }
+}
+
+/// An event decoded from the output of `RawReporter`
+#[derive(Clone, Debug, PartialEq)]
+// events are decoded one line at a time and consumed immediately
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum RawEvent {
+    Start {
+        group: String,
+        name: String,
+        /// `PROTOCOL_VERSION` of the child process, or 0 if it did not write
+        /// one
+        version: u32,
+    },
+    Complete {
+        group: String,
+        name: String,
+        result: Result<BenchmarkResult, Error>,
+    },
+    Terminated,
+    Finish,
+}
+
+/// Encode the error of the failed benchmark into a single line
+pub(crate) fn format_error_line(group: &str, name: &str, error: &Error) -> String {
+    let kind = match error {
+        Error::InvalidBenchmarkFunction(_) => "invalid",
+        Error::BenchmarkPanicked(_) => "panicked",
+        Error::TimedOut(_) => "timeout",
+        Error::Interrupted => "interrupted",
+        Error::ProcessFailed(_) => "process",
+    };
+    let message = format!("{:?}", error);
+
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        ERROR_PREFIX,
+        group,
+        name,
+        kind,
+        escape(&message)
+    )
+}
+
+/// Encode the result of the benchmark into a single line.
+///
+/// Measurements are followed by optional `key=value` fields, which are omitted
+/// if empty.
+pub(crate) fn format_result_line(group: &str, name: &str, result: &BenchmarkResult) -> String {
+    fn join<T, F: Fn(&T) -> String>(values: &[T], f: F) -> String {
+        let values: Vec<String> = values.iter().map(f).collect();
+        values.join(" ")
+    }
+
+    let mut line = format!(
+        "{}\t{}\t{}\t{}",
+        RESULT_PREFIX,
+        group,
+        name,
+        join(&result.measurements, |(k, t)| format!("{}:{:e}", k, t))
+    );
+
+    let mut push_field = |key: &str, value: String| {
+        if !value.is_empty() {
+            line.push_str(&format!("\t{}={}", key, value));
+        }
+    };
+    push_field("wall_times", join(&result.wall_times, |t| format!("{:e}", t)));
+    for (name, values) in result.counters.iter() {
+        push_field(
+            &format!("{}{}", COUNTER_PREFIX, name),
+            join(values, |v| v.to_string()),
+        );
+    }
+    for (name, unit, values) in result.metrics.iter() {
+        push_field(
+            &format!("{}{}.{}", METRIC_PREFIX, unit.as_str(), name),
+            join(values, |v| v.to_string()),
+        );
+    }
+    push_field("layouts", join(&result.layouts, |n| n.to_string()));
+    push_field("runs", join(&result.runs, |n| n.to_string()));
+    push_field(
+        "first_calls",
+        join(&result.first_calls, |t| format!("{:e}", t)),
+    );
+    push_field(
+        "input_pool",
+        result.input_pool.map_or(String::new(), |n| n.to_string()),
+    );
+    push_field(
+        "latency",
+        result.latency.as_ref().map_or(String::new(), |h| h.to_string()),
+    );
+    push_field(
+        "stack",
+        result.stack.map_or(String::new(), |n| n.to_string()),
+    );
+    for rate in result.rates.iter() {
+        push_field(
+            &format!("{}{}", RATE_PREFIX, rate.rate),
+            format!("{:e} {}", rate.elapsed, rate.latency),
+        );
+    }
+    push_field(
+        "empty_loop",
+        result
+            .empty_loop
+            .map_or(String::new(), |(m, s)| format!("{:e} {:e}", m, s)),
+    );
+
+    line
+}
+
+/// Decode a line written by `RawReporter`. Returns `None` if the line was not
+/// written by `RawReporter` (e.g. printed by the benchmark itself).
+pub(crate) fn parse_raw_line(line: &str) -> Option<RawEvent> {
+    let mut fields = line.split('\t');
+    let prefix = fields.next()?;
+    if prefix == FINISH_PREFIX {
+        return Some(RawEvent::Finish);
+    }
+
+    let group = fields.next()?.to_owned();
+    let name = fields.next()?.to_owned();
+
+    let result = match prefix {
+        START_PREFIX => {
+            let version = match fields.next() {
+                Some(version) => version.parse().ok()?,
+                None => 0,
+            };
+            return Some(RawEvent::Start {
+                group,
+                name,
+                version,
+            });
+        }
+        TERMINATED_PREFIX => return Some(RawEvent::Terminated),
+        RESULT_PREFIX => {
+            let measurements = fields
+                .next()?
+                .split_whitespace()
+                .map(|m| {
+                    let idx = m.find(':')?;
+                    Some((m[..idx].parse().ok()?, m[idx + 1..].parse().ok()?))
+                })
+                .collect::<Option<Vec<(usize, f64)>>>()?;
+
+            let mut result = BenchmarkResult {
+                measurements,
+                wall_times: Vec::new(),
+                counters: Vec::new(),
+                metrics: Vec::new(),
+                runs: Vec::new(),
+                layouts: Vec::new(),
+                first_calls: Vec::new(),
+                input_pool: None,
+                latency: None,
+                rates: Vec::new(),
+                stack: None,
+                empty_loop: None,
+            };
+
+            for field in fields {
+                let idx = field.find('=')?;
+                let value = &field[idx + 1..];
+                match &field[..idx] {
+                    "wall_times" => result.wall_times = parse_list(value)?,
+                    "layouts" => result.layouts = parse_list(value)?,
+                    "runs" => result.runs = parse_list(value)?,
+                    "first_calls" => result.first_calls = parse_list(value)?,
+                    "input_pool" => result.input_pool = Some(value.parse().ok()?),
+                    "latency" => result.latency = Some(value.parse().ok()?),
+                    "stack" => result.stack = Some(value.parse().ok()?),
+                    key if key.starts_with(COUNTER_PREFIX) => {
+                        let name = key[COUNTER_PREFIX.len()..].to_owned();
+                        result.counters.push((name, parse_list(value)?));
+                    }
+                    key if key.starts_with(METRIC_PREFIX) => {
+                        let key = &key[METRIC_PREFIX.len()..];
+                        let idx = key.find('.')?;
+                        let unit = key[..idx].parse().ok()?;
+                        let name = key[idx + 1..].to_owned();
+                        result.metrics.push((name, unit, parse_list(value)?));
+                    }
+                    key if key.starts_with(RATE_PREFIX) => {
+                        let idx = value.find(' ')?;
+                        result.rates.push(RateResult {
+                            rate: key[RATE_PREFIX.len()..].parse().ok()?,
+                            elapsed: value[..idx].parse().ok()?,
+                            latency: value[idx + 1..].parse().ok()?,
+                        });
+                    }
+                    "empty_loop" => match *parse_list::<f64>(value)? {
+                        [mean, stderr] => result.empty_loop = Some((mean, stderr)),
+                        _ => return None,
+                    },
+                    // fields written by other versions
+                    _ => {}
+                }
+            }
+
+            Ok(result)
+        }
+        ERROR_PREFIX => {
+            let kind = fields.next()?;
+            let message = unescape(fields.next()?);
+            Err(match kind {
+                "invalid" => Error::InvalidBenchmarkFunction(message),
+                "panicked" => Error::BenchmarkPanicked(message),
+                "timeout" => Error::TimedOut(message),
+                "interrupted" => Error::Interrupted,
+                _ => Error::ProcessFailed(message),
+            })
+        }
+        _ => return None,
+    };
+
+    Some(RawEvent::Complete {
+        group,
+        name,
+        result,
+    })
+}
+
+fn parse_list<T: std::str::FromStr>(s: &str) -> Option<Vec<T>> {
+    s.split_whitespace().map(|v| v.parse().ok()).collect()
+}
+
+/// Escape backslashes, tabs and newlines so that `s` fits into a single field
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::histogram::Histogram;
+    use crate::measurement::Unit;
+
+    #[test]
+    fn parse_line() {
+        let mut latency = Histogram::new();
+        latency.record(0.0);
+        latency.record(1.5);
+        let result = BenchmarkResult {
+            measurements: vec![(10, 1.5e-6), (20, 3.25e-6)],
+            wall_times: vec![2e-6, 4e-6],
+            counters: vec![("instructions".to_owned(), vec![120.0, 240.0])],
+            metrics: vec![("compression_ratio".to_owned(), Unit::Ratio, vec![3.5, 3.25])],
+            runs: Vec::new(),
+            layouts: vec![0, 1],
+            first_calls: vec![2.5e-5],
+            input_pool: Some(16),
+            latency: Some(latency.clone()),
+            rates: vec![RateResult {
+                rate: 1000.0,
+                elapsed: 0.5,
+                latency,
+            }],
+            stack: Some(2048),
+            empty_loop: Some((2.5e-10, 1e-12)),
+        };
+        let line = format_result_line("group", "bench", &result);
+        assert_eq!(
+            line,
+            "smbench:result\tgroup\tbench\t10:1.5e-6 20:3.25e-6\twall_times=2e-6 4e-6\t\
+            counter.instructions=120 240\tmetric.ratio.compression_ratio=3.5 3.25\t\
+            layouts=0 1\t\
+            first_calls=2.5e-5\tinput_pool=16\tlatency=1.5e0 0:1 131008:1\tstack=2048\t\
+            rate.1000=5e-1 1.5e0 0:1 131008:1\tempty_loop=2.5e-10 1e-12"
+        );
+
+        let event = parse_raw_line(&line);
+        assert_eq!(
+            event,
+            Some(RawEvent::Complete {
+                group: "group".to_owned(),
+                name: "bench".to_owned(),
+                result: Ok(result),
+            })
+        );
+
+        let event = parse_raw_line("smbench:error\tgroup\tbench\tpanicked\tline 1\\nline\\\\2");
+        assert_eq!(
+            event,
+            Some(RawEvent::Complete {
+                group: "group".to_owned(),
+                name: "bench".to_owned(),
+                result: Err(Error::BenchmarkPanicked("line 1\nline\\2".to_owned())),
+            })
+        );
+
+        assert_eq!(
+            parse_raw_line("smbench:start\tgroup\tbench\t1"),
+            Some(RawEvent::Start {
+                group: "group".to_owned(),
+                name: "bench".to_owned(),
+                version: 1,
+            })
+        );
+        assert_eq!(
+            parse_raw_line("smbench:start\tgroup\tbench"),
+            Some(RawEvent::Start {
+                group: "group".to_owned(),
+                name: "bench".to_owned(),
+                version: 0,
+            })
+        );
+        assert_eq!(parse_raw_line("smbench:finish"), Some(RawEvent::Finish));
+
+        assert_eq!(parse_raw_line("hello from benchmark"), None);
+        assert_eq!(parse_raw_line("smbench:result\tgroup\tbench\t10:abc"), None);
+    }
+
+    #[test]
+    fn escape_roundtrip() {
+        let s = "a\tb\\nc\nd\\";
+        assert_eq!(unescape(&escape(s)), s);
+    }
+}
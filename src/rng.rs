@@ -0,0 +1,163 @@
+use std::ops::Range;
+
+/// Small and fast pseudo random number generator (SplitMix64)
+///
+/// The generator returned by `Bencher::rng()` is seeded from `--seed` and the
+/// name of the benchmark, so benchmarks generate the same inputs on every run
+/// and every machine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    #[inline]
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    #[inline]
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        mix(self.state)
+    }
+
+    /// Uniformly distributed float in `[0, 1)`
+    #[inline]
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+
+    /// Uniformly distributed integer in `range`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is empty.
+    pub fn gen_range<T: RandomInt>(&mut self, range: Range<T>) -> T {
+        assert!(range.start < range.end, "empty range");
+        let width = (range.end.to_i128() - range.start.to_i128()) as u64;
+        T::from_i128(range.start.to_i128() + self.below(width) as i128)
+    }
+
+    /// Fill `buf` with random bytes
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    /// Buffer of `len` random bytes
+    pub fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        self.fill_bytes(&mut buf);
+        buf
+    }
+
+    /// Vector of `len` integers uniformly distributed in `range`
+    pub fn integers<T: RandomInt>(&mut self, len: usize, range: Range<T>) -> Vec<T> {
+        (0..len).map(|_| self.gen_range(range.clone())).collect()
+    }
+
+    /// String of `len` random ASCII alphanumeric characters
+    pub fn string(&mut self, len: usize) -> String {
+        const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+        (0..len)
+            .map(|_| CHARS[self.below(CHARS.len() as u64) as usize] as char)
+            .collect()
+    }
+
+    /// Uniformly distributed integer in `[0, n)` without modulo bias
+    fn below(&mut self, n: u64) -> u64 {
+        // Lemire's multiply-and-shift method
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let m = self.next_u64() as u128 * n as u128;
+            if (m as u64) >= threshold {
+                return (m >> 64) as u64;
+            }
+        }
+    }
+}
+
+/// Integer types which can be generated by `Rng::gen_range`
+pub trait RandomInt: Copy + PartialOrd {
+    #[doc(hidden)]
+    fn to_i128(self) -> i128;
+    #[doc(hidden)]
+    fn from_i128(v: i128) -> Self;
+}
+
+macro_rules! impl_random_int {
+    ($($t:ty),*) => {
+        $(
+            impl RandomInt for $t {
+                #[inline]
+                fn to_i128(self) -> i128 {
+                    self as i128
+                }
+
+                #[inline]
+                fn from_i128(v: i128) -> Self {
+                    v as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_random_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+pub(crate) const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Output function of SplitMix64
+#[inline]
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// 64-bit FNV-1a hash of `key`, which does not depend on the Rust version or
+/// the platform
+pub(crate) fn stable_hash(key: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rng;
+
+    #[test]
+    fn splitmix64() {
+        // reference values from the original implementation
+        let mut rng = Rng::new(1234567);
+        assert_eq!(rng.next_u64(), 6457827717110365317);
+        assert_eq!(rng.next_u64(), 3203168211198807973);
+        assert_eq!(rng.next_u64(), 9817491932198370423);
+    }
+
+    #[test]
+    fn helpers() {
+        let mut rng = Rng::new(42);
+        assert_eq!(rng.bytes(13).len(), 13);
+        assert!(rng.string(100).bytes().all(|c| c.is_ascii_alphanumeric()));
+        assert!(rng
+            .integers(1000, -5i32..5)
+            .iter()
+            .all(|v| (-5..5).contains(v)));
+        assert_eq!(rng.gen_range(7u8..8), 7);
+        assert!(rng.gen_range(i64::MIN..i64::MAX) < i64::MAX);
+
+        let x = rng.next_f64();
+        assert!((0.0..1.0).contains(&x));
+
+        // same seed, same sequence
+        assert_eq!(Rng::new(7).string(32), Rng::new(7).string(32));
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Install Ctrl-C handler. The first Ctrl-C only sets the interrupted flag so
+/// that benchmarks can stop gracefully, and the second one kills the process
+/// immediately.
+pub(crate) fn install_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe { imp::install() });
+}
+
+/// Returns true if Ctrl-C was pressed
+#[inline]
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+mod imp {
+    use super::INTERRUPTED;
+    use std::os::raw::c_int;
+    use std::sync::atomic::Ordering;
+
+    const SIGINT: c_int = 2;
+
+    extern "C" fn handler(_signum: c_int) {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            unsafe { ffi::_exit(130) }
+        }
+    }
+
+    pub(super) unsafe fn install() {
+        ffi::signal(SIGINT, handler as extern "C" fn(c_int) as usize);
+    }
+
+    mod ffi {
+        use std::os::raw::c_int;
+
+        extern "C" {
+            pub fn signal(signum: c_int, handler: usize) -> usize;
+            pub fn _exit(status: c_int) -> !;
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::INTERRUPTED;
+    use std::sync::atomic::Ordering;
+
+    const CTRL_C_EVENT: u32 = 0;
+
+    unsafe extern "system" fn handler(ctrl_type: u32) -> i32 {
+        if ctrl_type != CTRL_C_EVENT {
+            return 0;
+        }
+
+        // returning FALSE passes the event to the default handler, which
+        // terminates the process
+        !INTERRUPTED.swap(true, Ordering::SeqCst) as i32
+    }
+
+    pub(super) unsafe fn install() {
+        ffi::SetConsoleCtrlHandler(Some(handler), 1);
+    }
+
+    mod ffi {
+        extern "system" {
+            pub fn SetConsoleCtrlHandler(
+                handler: Option<unsafe extern "system" fn(u32) -> i32>,
+                add: i32,
+            ) -> i32;
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    pub(super) unsafe fn install() {}
+}
@@ -0,0 +1,100 @@
+use std::ptr;
+
+use crate::common::black_box;
+
+/// Size of the stack of the thread which measures stack usage
+pub(crate) const STACK_SIZE: usize = 8 << 20;
+
+/// Top part of the stack which is not painted, which holds the frames of the
+/// thread itself (e.g. thread-local storage and the start routine)
+const STACK_MARGIN: usize = 256 << 10;
+
+/// Bytes below the stack pointer which may be used by leaf functions without
+/// moving the stack pointer (the red zone of the x86_64 System V ABI and more)
+const RED_ZONE: usize = 512;
+
+const PAINT: usize = 0x5a5a_5a5a_5a5a_5a5a_u64 as usize;
+
+/// Call `f` and return the maximum depth of the stack in bytes below the
+/// caller of `f`.
+///
+/// The unused part of the stack is painted with a pattern before `f` is called
+/// and scanned for the deepest overwritten word afterwards. The current thread
+/// must have a stack of `STACK_SIZE` bytes.
+#[inline(never)]
+pub(crate) fn high_water_mark(f: &mut dyn FnMut()) -> usize {
+    let marker = 0u8;
+    let top = black_box(&marker) as *const u8 as usize;
+    let align = std::mem::size_of::<usize>();
+    let bottom = (top - (STACK_SIZE - STACK_MARGIN) + align - 1) & !(align - 1);
+
+    let start = paint(bottom);
+    f();
+
+    let mut addr = bottom;
+    while addr < start && unsafe { ptr::read_volatile(addr as *const usize) } == PAINT {
+        addr += align;
+    }
+    top.saturating_sub(addr)
+}
+
+/// Paint the stack from the aligned address `bottom` up to the frame of this
+/// function and return the end of the painted region
+#[inline(never)]
+fn paint(bottom: usize) -> usize {
+    let marker = 0u8;
+    let sp = black_box(&marker) as *const u8 as usize;
+    let align = std::mem::size_of::<usize>();
+    let start = (sp - RED_ZONE) & !(align - 1);
+
+    let mut addr = bottom;
+    while addr < start {
+        // the region below the stack pointer belongs to this thread but is not
+        // used by any frame until `f` is called
+        unsafe { ptr::write_volatile(addr as *mut usize, PAINT) };
+        addr += align;
+    }
+    start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Recurse `depth` times with a frame of at least 256 bytes
+    #[inline(never)]
+    fn recurse(depth: usize) -> usize {
+        let frame = [depth as u8; 256];
+        let frame = black_box(&frame);
+        if depth == 0 {
+            return frame[0] as usize;
+        }
+        recurse(depth - 1) + frame[255] as usize
+    }
+
+    #[test]
+    fn grows_with_depth() {
+        let depths = [0, 10, 100, 1000];
+        let marks = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                depths
+                    .iter()
+                    .map(|&depth| {
+                        high_water_mark(&mut || {
+                            black_box(recurse(depth));
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        for (pair, depths) in marks.windows(2).zip(depths.windows(2)) {
+            assert!(pair[0] < pair[1], "{:?} at depths {:?}", marks, depths);
+        }
+        assert!(marks[3] >= 1000 * 256, "{:?}", marks);
+    }
+}
@@ -2,7 +2,9 @@ mod distribution;
 mod functions;
 mod outliers;
 mod regression;
+mod steady;
 
 pub use distribution::*;
 pub use outliers::*;
 pub use regression::*;
+pub use steady::*;
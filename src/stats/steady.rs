@@ -0,0 +1,43 @@
+/// Coefficient of variation (standard deviation divided by mean) of `data`
+pub fn coefficient_of_variation(data: &[f64]) -> f64 {
+    let n = data.len() as f64;
+    let mean = data.iter().sum::<f64>() / n;
+    let variance = data.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+
+    variance.sqrt() / mean
+}
+
+/// Whether `window` (e.g. time per iteration of consecutive batches) looks
+/// stationary. The coefficient of variation must be below `max_cv`, and the
+/// means of the first and second halves of the window must not differ by more
+/// than `max_cv` relative to the mean, which rejects slow trends.
+pub fn is_steady_state(window: &[f64], max_cv: f64) -> bool {
+    if window.len() < 4 {
+        return false;
+    }
+
+    let (first, second) = window.split_at(window.len() / 2);
+    let mean = |x: &[f64]| x.iter().sum::<f64>() / x.len() as f64;
+    let trend = (mean(second) - mean(first)).abs() / mean(window);
+
+    coefficient_of_variation(window) < max_cv && trend < max_cv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steady_state() {
+        let steady = [1.0, 1.01, 0.99, 1.0, 1.02, 0.98, 1.0, 1.0];
+        assert!(is_steady_state(&steady, 0.02));
+
+        // noisy
+        let noisy = [1.0, 1.3, 0.8, 1.0, 1.2, 0.7, 1.0, 1.1];
+        assert!(!is_steady_state(&noisy, 0.02));
+
+        // still getting faster
+        let trend = [1.1, 1.09, 1.08, 1.07, 1.06, 1.05, 1.04, 1.03];
+        assert!(!is_steady_state(&trend, 0.02));
+    }
+}
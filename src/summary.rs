@@ -1,29 +1,466 @@
-use crate::stats::{self, Normal, Regression};
+use std::fmt;
+
+use crate::calibration;
+use crate::histogram::Histogram;
+use crate::measurement::Unit;
+use crate::stats::{self, Distribution, Normal, Regression};
 use crate::BenchmarkResult;
 
+/// Number of standard errors by which a benchmark must be slower than an
+/// empty loop
+const EMPTY_LOOP_MARGIN: f64 = 3.0;
+
+/// Percentiles of single calls reported in latency mode
+const PERCENTILES: &[(&str, f64)] = &[
+    ("p50", 0.5),
+    ("p90", 0.9),
+    ("p99", 0.99),
+    ("p99.9", 0.999),
+];
+
 #[derive(Debug)]
 pub struct Summary {
-    pub elapsed_time: Normal,
+    /// Value per iteration estimated from the samples. `None` in open-loop
+    /// mode, which takes a single sample per rate and reports only `rates`.
+    pub elapsed_time: Option<Normal>,
+    /// Variance components of the elapsed time across processes. Only present
+    /// if the benchmark was measured in more than one process.
+    pub processes: Option<VarianceComponents>,
+    /// Variance components of the elapsed time across randomized memory
+    /// layouts. The samples of each layout are interleaved with those of the
+    /// other layouts, so drift during the run shows up within layouts rather
+    /// than between them. Only present if the memory layout was randomized.
+    pub layouts: Option<VarianceComponents>,
+    /// Mean time of the i-th call of the routine across processes, measured
+    /// before warm-up. Empty if not measured.
+    pub first_calls: Vec<f64>,
+    /// Set if the elapsed time is too short to be meaningful
+    pub warning: Option<Warning>,
+    /// Time per iteration of an empty loop subtracted from the elapsed time
+    pub overhead: Option<Normal>,
+    /// Wall-clock time per iteration, if the benchmark measured something else
+    pub wall_time: Option<Normal>,
+    /// Count of each performance counter and reported counter per iteration
+    pub counters: Vec<(String, Normal)>,
+    /// Mean of each reported metric over the samples and its unit
+    pub metrics: Vec<(String, Unit, Normal)>,
+    /// Percentiles and the maximum of single calls, measured in latency mode.
+    /// Empty otherwise.
+    pub latency: Vec<(&'static str, f64)>,
+    /// Latency at each offered rate, measured in open-loop mode
+    pub rates: Vec<RateSummary>,
+}
+
+#[derive(Debug)]
+pub struct RateSummary {
+    /// offered calls per second
+    pub rate: f64,
+    /// completed calls per second
+    pub throughput: f64,
+    /// whether the throughput fell behind the offered rate
+    pub saturated: bool,
+    /// percentiles and the maximum of the latency in seconds
+    pub latency: Vec<(&'static str, f64)>,
+}
+
+/// Reason why the measured time is probably meaningless, usually because the
+/// optimizer removed the benchmarked code
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Warning {
+    /// less than about one CPU cycle per iteration
+    BelowCycle,
+    /// not significantly slower than an empty loop
+    EmptyLoop,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Warning::BelowCycle => "less than one CPU cycle per iteration",
+            Warning::EmptyLoop => "indistinguishable from an empty loop",
+        };
+        write!(f, "{}; the optimizer probably removed the benchmarked code", reason)
+    }
+}
+
+/// Decomposition of the variance of an estimate into the variance inside each
+/// run and the extra variance between runs (one-way random effects model).
+#[derive(Clone, Debug, PartialEq)]
+pub struct VarianceComponents {
+    pub within: f64,
+    pub between: f64,
+}
+
+impl VarianceComponents {
+    /// Estimate variance components from the estimates of each run, and
+    /// return them with the pooled estimate.
+    pub fn from_estimates(estimates: &[Normal]) -> (Normal, VarianceComponents) {
+        let k = estimates.len() as f64;
+        let mean = estimates.iter().map(|e| e.mean()).sum::<f64>() / k;
+        let within = estimates.iter().map(|e| e.variance()).sum::<f64>() / k;
+        let observed = estimates
+            .iter()
+            .map(|e| (e.mean() - mean).powi(2))
+            .sum::<f64>()
+            / (k - 1.0);
+
+        let components = VarianceComponents {
+            within,
+            between: f64::max(observed - within, 0.0),
+        };
+
+        (
+            Normal::new(mean, (f64::max(observed, within) / k).sqrt()),
+            components,
+        )
+    }
+
+    fn average(components: &[VarianceComponents]) -> Option<VarianceComponents> {
+        if components.is_empty() {
+            return None;
+        }
+
+        let k = components.len() as f64;
+        Some(VarianceComponents {
+            within: components.iter().map(|c| c.within).sum::<f64>() / k,
+            between: components.iter().map(|c| c.between).sum::<f64>() / k,
+        })
+    }
 }
 
-pub fn summarize(result: &BenchmarkResult) -> Summary {
-    let mut sec_per_iters: Vec<f64> = result
+/// Summarize the result of the benchmark. If `subtract_overhead` is set, the
+/// time of an empty loop is subtracted from the elapsed time, and its
+/// uncertainty is added to the uncertainty of the elapsed time.
+pub fn summarize(result: &BenchmarkResult, subtract_overhead: bool) -> Summary {
+    let first_calls = summarize_first_calls(result);
+    let metrics = result
+        .metrics
+        .iter()
+        .map(|(name, unit, values)| (name.clone(), *unit, mean(values)))
+        .collect();
+    let rates = result
+        .rates
+        .iter()
+        .map(|r| RateSummary {
+            rate: r.rate,
+            throughput: r.throughput(),
+            saturated: r.is_saturated(),
+            latency: percentiles(&r.latency),
+        })
+        .collect();
+
+    // a sample per rate cannot estimate a value per iteration
+    if !result.rates.is_empty() {
+        return Summary {
+            elapsed_time: None,
+            processes: None,
+            layouts: None,
+            first_calls,
+            warning: None,
+            overhead: None,
+            wall_time: None,
+            counters: Vec::new(),
+            metrics,
+            latency: Vec::new(),
+            rates,
+        };
+    }
+
+    let mut layouts = result.layouts.iter();
+    let mut layout_components = Vec::new();
+
+    let mut estimates: Vec<Normal> = result
+        .runs()
+        .map(|run| {
+            if result.layouts.is_empty() {
+                return estimate(run);
+            }
+
+            // group the samples measured with the same memory layout, which
+            // are spread over the run
+            let mut groups: Vec<Vec<(usize, f64)>> = Vec::new();
+            for (&sample, &layout) in run.iter().zip(layouts.by_ref()) {
+                if groups.len() <= layout {
+                    groups.resize(layout + 1, Vec::new());
+                }
+                groups[layout].push(sample);
+            }
+
+            let per_layout: Vec<Normal> = groups
+                .iter()
+                .filter(|group| !group.is_empty())
+                .map(|group| estimate(group))
+                .collect();
+            if per_layout.len() < 2 {
+                return estimate(run);
+            }
+
+            let (pooled, components) = VarianceComponents::from_estimates(&per_layout);
+            layout_components.push(components);
+            pooled
+        })
+        .collect();
+
+    let layouts = VarianceComponents::average(&layout_components);
+
+    let (elapsed_time, processes) = if estimates.len() < 2 {
+        (estimates.pop().unwrap(), None)
+    } else {
+        let (elapsed_time, components) = VarianceComponents::from_estimates(&estimates);
+        (elapsed_time, Some(components))
+    };
+
+    let warning = detect_warning(result, &elapsed_time);
+    let overhead = result
+        .empty_loop
+        .filter(|_| subtract_overhead)
+        .map(|(mean, stderr)| Normal::new(mean, stderr));
+    let elapsed_time = match overhead {
+        Some(ref o) => Normal::new(
+            elapsed_time.mean() - o.mean(),
+            (elapsed_time.variance() + o.variance()).sqrt(),
+        ),
+        None => elapsed_time,
+    };
+
+    let wall_time = if result.wall_times.is_empty() {
+        None
+    } else {
+        Some(estimate_values(result, &result.wall_times))
+    };
+    let counters = result
+        .counters
+        .iter()
+        .map(|(name, values)| (name.clone(), estimate_values(result, values)))
+        .collect();
+    let latency = result.latency.as_ref().map_or(Vec::new(), percentiles);
+
+    Summary {
+        elapsed_time: Some(elapsed_time),
+        processes,
+        layouts,
+        first_calls,
+        warning,
+        overhead,
+        wall_time,
+        counters,
+        metrics,
+        latency,
+        rates,
+    }
+}
+
+fn percentiles(histogram: &Histogram) -> Vec<(&'static str, f64)> {
+    PERCENTILES
+        .iter()
+        .map(|&(label, q)| (label, histogram.quantile(q)))
+        .chain(Some(("max", histogram.max())))
+        .collect()
+}
+
+fn detect_warning(result: &BenchmarkResult, elapsed_time: &Normal) -> Option<Warning> {
+    // the empty loop is measured only if values are times
+    let (empty_mean, empty_stderr) = result.empty_loop?;
+
+    let mean = elapsed_time.mean();
+    if mean < calibration::cycle_time() {
+        return Some(Warning::BelowCycle);
+    }
+
+    let stderr = (elapsed_time.variance() + empty_stderr * empty_stderr).sqrt();
+    if mean - empty_mean <= EMPTY_LOOP_MARGIN * stderr {
+        return Some(Warning::EmptyLoop);
+    }
+
+    None
+}
+
+fn summarize_first_calls(result: &BenchmarkResult) -> Vec<f64> {
+    // every process measures the same number of first calls
+    let runs = result.runs.len().max(1);
+    let k = result.first_calls.len() / runs;
+
+    (0..k)
+        .map(|i| {
+            let calls = result.first_calls.iter().skip(i).step_by(k);
+            calls.sum::<f64>() / runs as f64
+        })
+        .collect()
+}
+
+/// Estimate the value per iteration of `values` measured with the samples of
+/// `result`
+fn estimate_values(result: &BenchmarkResult, values: &[f64]) -> Normal {
+    let samples: Vec<(usize, f64)> = result
         .measurements
         .iter()
-        .map(|(i, t)| t / *i as f64)
+        .zip(values)
+        .map(|(&(i, _), &v)| (i, v))
         .collect();
+    estimate(&samples)
+}
+
+/// Mean of `values` and its standard error
+fn mean(values: &[f64]) -> Normal {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return Normal::new(mean, 0.0);
+    }
+
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    Normal::new(mean, (variance / n).sqrt())
+}
+
+fn estimate(measurements: &[(usize, f64)]) -> Normal {
+    let mut sec_per_iters: Vec<f64> = measurements.iter().map(|(i, t)| t / *i as f64).collect();
 
     let (l, r) = stats::outlier_bound(&mut sec_per_iters, 3.0);
-    let data_iter = result.measurements.iter().filter(|&(i, t)| {
+    let data_iter = measurements.iter().filter(|&(i, t)| {
         let sec_per_iter = t / *i as f64;
         l <= sec_per_iter && sec_per_iter <= r
     });
 
-    let x: Vec<f64> = data_iter.clone().map(|(i, _)| *i as f64).collect();
-    let y: Vec<f64> = data_iter.map(|(_, t)| *t).collect();
-    let slope = stats::LeastSquare.slope(&x, &y);
+    let mut x: Vec<f64> = data_iter.clone().map(|(i, _)| *i as f64).collect();
+    let mut y: Vec<f64> = data_iter.map(|(_, t)| *t).collect();
 
-    Summary {
-        elapsed_time: slope,
+    // too few samples to exclude outliers
+    if x.len() < 2 {
+        x = measurements.iter().map(|(i, _)| *i as f64).collect();
+        y = measurements.iter().map(|(_, t)| *t).collect();
+    }
+
+    stats::LeastSquare.slope(&x, &y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::RateResult;
+    use assert_float_eq::*;
+
+    #[test]
+    fn variance_components() {
+        let estimates = [
+            Normal::new(1.0, 0.1),
+            Normal::new(2.0, 0.1),
+            Normal::new(3.0, 0.1),
+        ];
+        let (pooled, components) = VarianceComponents::from_estimates(&estimates);
+        assert_float_absolute_eq!(pooled.mean(), 2.0);
+        assert_float_absolute_eq!(pooled.variance(), 1.0 / 3.0);
+        assert_float_absolute_eq!(components.within, 0.01);
+        assert_float_absolute_eq!(components.between, 0.99);
+
+        // between-run variance is never negative
+        let estimates = [Normal::new(1.0, 1.0), Normal::new(1.0, 1.0)];
+        let (_, components) = VarianceComponents::from_estimates(&estimates);
+        assert_float_absolute_eq!(components.between, 0.0);
+    }
+
+    #[test]
+    fn warning() {
+        let mut result = BenchmarkResult {
+            measurements: (1..=10).map(|i| (i * 100, i as f64 * 1e-6)).collect(),
+            wall_times: Vec::new(),
+            counters: Vec::new(),
+            metrics: Vec::new(),
+            runs: Vec::new(),
+            layouts: Vec::new(),
+            first_calls: Vec::new(),
+            input_pool: None,
+            latency: None,
+            rates: Vec::new(),
+            stack: None,
+            empty_loop: Some((1e-9, 1e-12)),
+        };
+        assert_eq!(summarize(&result, false).warning, None);
+
+        result.empty_loop = Some((1e-8, 1e-10));
+        assert_eq!(summarize(&result, false).warning, Some(Warning::EmptyLoop));
+
+        result.measurements = (1..=10).map(|i| (i * 100, i as f64 * 1e-12)).collect();
+        assert_eq!(summarize(&result, false).warning, Some(Warning::BelowCycle));
+    }
+
+    #[test]
+    fn layouts() {
+        // layout 1 is 20% slower than layout 0, and samples alternate layouts
+        let per_iter = |i: usize| if i % 2 == 0 { 1e-8 } else { 1.2e-8 };
+        let result = BenchmarkResult {
+            measurements: (1..=16).map(|i| (i * 100, (i * 100) as f64 * per_iter(i))).collect(),
+            wall_times: Vec::new(),
+            counters: Vec::new(),
+            metrics: Vec::new(),
+            runs: Vec::new(),
+            layouts: (1..=16).map(|i| i % 2).collect(),
+            first_calls: Vec::new(),
+            input_pool: None,
+            latency: None,
+            rates: Vec::new(),
+            stack: None,
+            empty_loop: None,
+        };
+
+        let summ = summarize(&result, false);
+        assert_float_absolute_eq!(summ.elapsed_time.unwrap().mean(), 1.1e-8, 1e-15);
+        let layouts = summ.layouts.unwrap();
+        assert_float_absolute_eq!(layouts.within, 0.0, 1e-24);
+        assert!(layouts.between > 1e-18, "{:?}", layouts);
+    }
+
+    #[test]
+    fn subtract_overhead() {
+        let result = BenchmarkResult {
+            measurements: (1..=10).map(|i| (i * 100, i as f64 * 1e-6)).collect(),
+            wall_times: Vec::new(),
+            counters: Vec::new(),
+            metrics: Vec::new(),
+            runs: Vec::new(),
+            layouts: Vec::new(),
+            first_calls: Vec::new(),
+            input_pool: None,
+            latency: None,
+            rates: Vec::new(),
+            stack: None,
+            empty_loop: Some((1e-9, 1e-10)),
+        };
+
+        let summ = summarize(&result, true);
+        let elapsed_time = summ.elapsed_time.unwrap();
+        assert_float_absolute_eq!(elapsed_time.mean(), 9e-9, 1e-15);
+        assert_float_absolute_eq!(elapsed_time.variance(), 1e-20, 1e-24);
+        assert_eq!(summ.overhead, Some(Normal::new(1e-9, 1e-10)));
+        assert_eq!(summarize(&result, false).overhead, None);
+    }
+
+    #[test]
+    fn open_loop() {
+        let mut latency = Histogram::new();
+        latency.record(1e-6);
+        let result = BenchmarkResult {
+            measurements: vec![(1, 1e-6)],
+            wall_times: Vec::new(),
+            counters: Vec::new(),
+            metrics: Vec::new(),
+            runs: Vec::new(),
+            layouts: Vec::new(),
+            first_calls: Vec::new(),
+            input_pool: None,
+            latency: None,
+            rates: vec![RateResult {
+                rate: 1000.0,
+                elapsed: 1e-3,
+                latency,
+            }],
+            stack: None,
+            empty_loop: None,
+        };
+
+        // a single sample gives no estimate per iteration
+        let summ = summarize(&result, false);
+        assert_eq!(summ.elapsed_time, None);
+        assert_eq!(summ.rates.len(), 1);
+        assert_float_absolute_eq!(summ.rates[0].throughput, 1000.0);
     }
 }
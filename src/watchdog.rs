@@ -0,0 +1,163 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::common::{BenchmarkGroup, BenchmarkInfo};
+use crate::error::Error;
+use crate::fmt;
+use crate::reporter::format_error_line;
+
+/// Time given to a timed-out benchmark to notice the cancellation before the
+/// process exits
+const GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Benchmark watched by the watchdog
+struct Watched {
+    group: BenchmarkGroup,
+    info: BenchmarkInfo,
+    start: Instant,
+    timeout: Option<Duration>,
+    phase: &'static str,
+    /// error set when the time limit is exceeded
+    error: Option<Error>,
+}
+
+impl Watched {
+    fn deadline(&self) -> Option<Instant> {
+        self.timeout.map(|t| self.start + t)
+    }
+}
+
+static WATCHED: Mutex<Option<Watched>> = Mutex::new(None);
+static CONDVAR: Condvar = Condvar::new();
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Start the watchdog thread which cancels benchmarks running longer than the
+/// time limit.
+///
+/// Benchmarks check for the cancellation between samples and fail with
+/// `Error::TimedOut`, which is reported from the benchmark thread. A benchmark
+/// stuck in a single call cannot be stopped from outside, so the watchdog
+/// exits the process if the benchmark has not stopped after `GRACE_PERIOD`.
+/// Child processes (`child`) report the timeout to the parent process before
+/// exiting.
+pub(crate) fn spawn(child: bool) {
+    static SPAWN: Once = Once::new();
+    SPAWN.call_once(move || {
+        thread::Builder::new()
+            .name("smbench-watchdog".to_owned())
+            .spawn(move || run(child))
+            .expect("Failed to spawn watchdog thread");
+    });
+}
+
+/// Start watching the benchmark. `timeout` of `None` means no time limit.
+pub(crate) fn watch(group: &BenchmarkGroup, info: &BenchmarkInfo, timeout: Option<Duration>) {
+    *WATCHED.lock().unwrap() = Some(Watched {
+        group: group.clone(),
+        info: info.clone(),
+        start: Instant::now(),
+        timeout,
+        phase: "setup",
+        error: None,
+    });
+    CANCELLED.store(false, Ordering::Relaxed);
+    CONDVAR.notify_one();
+}
+
+/// Change the time limit of the watched benchmark
+pub(crate) fn set_timeout(timeout: Duration) {
+    if let Some(ref mut watched) = *WATCHED.lock().unwrap() {
+        watched.timeout = Some(timeout);
+    }
+    CONDVAR.notify_one();
+}
+
+/// Record the phase of the watched benchmark (e.g. "warm-up")
+pub(crate) fn set_phase(phase: &'static str) {
+    if let Some(ref mut watched) = *WATCHED.lock().unwrap() {
+        watched.phase = phase;
+    }
+}
+
+/// Stop watching the benchmark
+pub(crate) fn unwatch() {
+    *WATCHED.lock().unwrap() = None;
+    CANCELLED.store(false, Ordering::Relaxed);
+    CONDVAR.notify_one();
+}
+
+/// Returns true if the watched benchmark exceeded its time limit
+#[inline]
+pub(crate) fn cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// Returns the timeout error if the watched benchmark exceeded its time limit
+pub(crate) fn check() -> Result<(), Error> {
+    if !cancelled() {
+        return Ok(());
+    }
+    match *WATCHED.lock().unwrap() {
+        Some(Watched {
+            error: Some(ref error),
+            ..
+        }) => Err(error.clone()),
+        _ => Ok(()),
+    }
+}
+
+fn run(child: bool) {
+    let mut watched = WATCHED.lock().unwrap();
+
+    loop {
+        let deadline = match watched.as_ref().and_then(Watched::deadline) {
+            Some(deadline) if watched.as_ref().unwrap().error.is_some() => deadline + GRACE_PERIOD,
+            Some(deadline) => deadline,
+            None => {
+                watched = CONDVAR.wait(watched).unwrap();
+                continue;
+            }
+        };
+
+        let now = Instant::now();
+        if now < deadline {
+            watched = CONDVAR.wait_timeout(watched, deadline - now).unwrap().0;
+            continue;
+        }
+
+        let timed_out = watched.as_mut().unwrap();
+        if timed_out.error.is_none() {
+            // let the benchmark fail by itself
+            timed_out.error = Some(Error::TimedOut(format!(
+                "did not finish within {} during {}",
+                fmt::time(timed_out.timeout.unwrap().as_secs_f64()),
+                timed_out.phase
+            )));
+            CANCELLED.store(true, Ordering::Relaxed);
+            continue;
+        }
+
+        // Keep the lock until the process exits so that the benchmark thread
+        // cannot start another benchmark.
+        let error = timed_out.error.as_ref().unwrap();
+        if child {
+            let line = format_error_line(timed_out.group.name(), timed_out.info.name(), error);
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            writeln!(stdout, "{}", line).ok();
+            stdout.flush().ok();
+        } else {
+            eprintln!(
+                "\nerror: {}/{} {:?}, exiting",
+                timed_out.group.name(),
+                timed_out.info.name(),
+                error
+            );
+        }
+
+        std::process::exit(1);
+    }
+}